@@ -0,0 +1,107 @@
+use crate::layout::reflow;
+use crate::read_line;
+use colored::*;
+use crossterm::terminal;
+use twine_terminal_rs::markup::plain;
+use twine_terminal_rs::*;
+
+/// Rows shown per page when the terminal size is unknown.
+const PAGE: usize = 20;
+
+/// Renders a record as a single line without markup.
+fn render(runner: &Runner, record: &Record) -> String {
+    match record {
+        Record::Text(text) => reflow(&plain(text)),
+        Record::Dialogue { character, speech } => {
+            let name = runner
+                .character(character)
+                .map_or(character.as_str(), |data| data.display_name(character));
+            format!("{}: {}", name, reflow(&plain(&speech.text)))
+        }
+        Record::Choice(choice) => format!("> {}", choice),
+    }
+}
+
+/// Highlights each case-insensitive match of `query` in `line` in reverse video,
+/// leaving the line as it is if `color` is off.
+fn highlight(line: &str, query: &str, color: bool) -> String {
+    if query.is_empty() || !color {
+        return line.to_string();
+    }
+    // ASCII lowercasing keeps byte offsets the same as in the original line.
+    let lower = line.to_ascii_lowercase();
+    let query = query.to_ascii_lowercase();
+    let (mut out, mut last) = (String::new(), 0);
+    for (start, _) in lower.match_indices(&query) {
+        out.push_str(&line[last..start]);
+        // Only turn reverse video off, so that the style of the rest of the line is kept.
+        out.push_str(&format!(
+            "\x1b[7m{}\x1b[27m",
+            &line[start..start + query.len()]
+        ));
+        last = start + query.len();
+    }
+    out.push_str(&line[last..]);
+    out
+}
+
+/// Pages through the lines shown and choices made so far, keeping only those
+/// containing `query` if it is not empty.
+pub fn page(runner: &Runner, query: &str) {
+    let lower = query.to_ascii_lowercase();
+    let lines: Vec<(bool, String)> = runner
+        .history()
+        .iter()
+        .map(|record| (matches!(record, Record::Choice(_)), render(runner, record)))
+        .filter(|(_, line)| line.to_ascii_lowercase().contains(&lower))
+        .collect();
+    if lines.is_empty() {
+        let msg = match query {
+            "" => "Nothing has happened yet.".to_string(),
+            query => format!("Nothing matches '{}'.", query),
+        };
+        println!("{}", msg.dimmed());
+        return;
+    }
+    let color = colored::control::SHOULD_COLORIZE.should_colorize();
+    let rows = terminal::size().map_or(PAGE, |(_, rows)| (rows as usize).saturating_sub(2).max(1));
+    for (i, chunk) in lines.chunks(rows).enumerate() {
+        if i > 0 {
+            print!(
+                "{}",
+                format!(
+                    "-- {}/{}, Enter for more, q to stop --",
+                    i * rows,
+                    lines.len()
+                )
+                .dimmed()
+            );
+            if read_line(None).is_some_and(|input| input.trim() == "q") {
+                return;
+            }
+        }
+        for (choice, line) in chunk {
+            let line = highlight(line, query, color);
+            match choice {
+                true => println!("{}", line.cyan()),
+                false => println!("{}", line),
+            }
+        }
+    }
+    println!("{}", "-- end of log --".dimmed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(highlight("Hello there", "", true), "Hello there");
+        assert_eq!(highlight("Hello there", "THERE", false), "Hello there");
+        assert_eq!(
+            highlight("Ha ha!", "ha", true),
+            "\x1b[7mHa\x1b[27m \x1b[7mha\x1b[27m!"
+        );
+    }
+}
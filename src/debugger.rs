@@ -0,0 +1,235 @@
+use crate::layout::Layout;
+use crate::pacing::Pacing;
+use crate::{get_input, prompt_choice, show};
+use colored::*;
+use std::collections::BTreeSet;
+use std::fmt;
+use twine_terminal_rs::locale::split_id;
+use twine_terminal_rs::structs::State;
+use twine_terminal_rs::value::Value;
+use twine_terminal_rs::*;
+
+const HELP: &str = "Debugger commands:
+  step, s             run the next line (or just press enter)
+  continue, c         run until a breakpoint
+  break, b [P[:N]]    pause at passage P or its line N, or list breakpoints
+  delete, d P[:N]     remove a breakpoint
+  state, p [VAR]      print the state, or one variable
+  set VAR OP VALUE    modify the state, as in `set gold += 5`
+  jump, j P           jump to the start of passage P
+  branch N            take block N of the current branching line, or `none`
+  quit, q             stop debugging";
+
+/// A place to pause: the start of a passage, or one of its lines.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Breakpoint {
+    passage: String,
+    line: Option<usize>,
+}
+
+impl Breakpoint {
+    fn parse(text: &str) -> Self {
+        match text.rsplit_once(':') {
+            Some((passage, line)) if line.parse::<usize>().is_ok() => Self {
+                passage: passage.to_string(),
+                line: line.parse().ok(),
+            },
+            _ => Self {
+                passage: text.to_string(),
+                line: None,
+            },
+        }
+    }
+
+    fn hit(&self, passage: &str, line: usize) -> bool {
+        self.passage == passage && self.line.unwrap_or(0) == line
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.passage, line),
+            None => write!(f, "{}", self.passage),
+        }
+    }
+}
+
+/// Prints the events produced so far, including the ones players don't see.
+fn show_events(runner: &mut Runner, input: &mut String) {
+    while let Some(event) = runner.poll() {
+        show(runner, &event, &Pacing::default(), &Layout::default());
+        match event {
+            Event::PassageEntered(passage) => println!("{}", format!("-> {}", passage).dimmed()),
+            Event::StateChanged { var, value } => {
+                println!("{}", format!("{} = {:?}", var, value).dimmed())
+            }
+            Event::Choices(choices) => prompt_choice(runner, &choices, input),
+            _ => (),
+        }
+    }
+}
+
+/// Removes the line IDs from the text of a line, including choices and nested lines.
+fn strip_ids(yaml: serde_yaml::Value) -> serde_yaml::Value {
+    use serde_yaml::Value as Yaml;
+    match yaml {
+        Yaml::String(text) => Yaml::String(split_id(&text).0.to_string()),
+        Yaml::Sequence(items) => Yaml::Sequence(items.into_iter().map(strip_ids).collect()),
+        Yaml::Mapping(entries) => Yaml::Mapping(
+            entries
+                .into_iter()
+                .map(|(key, value)| (strip_ids(key), strip_ids(value)))
+                .collect(),
+        ),
+        yaml => yaml,
+    }
+}
+
+/// Prints the line about to run.
+fn show_position(runner: &Runner) {
+    let (passage, line) = runner.position();
+    let location = format!("{}:{}", passage, line).bold().magenta();
+    match runner.current_line() {
+        Some(current) => {
+            let yaml = serde_yaml::to_value(current)
+                .and_then(|yaml| serde_yaml::to_string(&strip_ids(yaml)))
+                .unwrap_or_default();
+            println!(
+                "{} {}",
+                location,
+                yaml.trim_start_matches("---\n").trim_end()
+            );
+        }
+        None => println!("{} {}", location, "(end of passage)".dimmed()),
+    }
+}
+
+fn print_state(runner: &Runner, var: Option<&str>) {
+    for (name, value) in runner.state() {
+        if var.is_none_or(|var| var == name) {
+            println!("{} = {:?}", name, value);
+        }
+    }
+}
+
+/// Parses the arguments of `set VAR OP VALUE`. The value may contain spaces.
+fn parse_set(args: &str) -> Result<State, ValidationError> {
+    match args.splitn(3, ' ').collect::<Vec<_>>()[..] {
+        [var, op, value] => {
+            let mut set = State::new();
+            set.insert(format!("{} {}", var, op), Value::parse(value)?);
+            Ok(set)
+        }
+        _ => Err(verror!("Expected `set VAR OP VALUE`.")),
+    }
+}
+
+/// Reads debugger commands while paused.
+/// Returns whether to pause again after the next line, or None to quit.
+fn prompt(
+    runner: &mut Runner,
+    breakpoints: &mut BTreeSet<Breakpoint>,
+    input: &mut String,
+) -> Option<bool> {
+    show_position(runner);
+    loop {
+        print!("{}", "(debug) ".magenta());
+        get_input(input);
+        let (command, args) = match input.trim().split_once(' ') {
+            Some((command, args)) => (command, args.trim()),
+            None => (input.trim(), ""),
+        };
+        let result = match command {
+            "" | "s" | "step" => return Some(true),
+            "c" | "continue" => return Some(false),
+            "q" | "quit" => return None,
+            "b" | "break" if args.is_empty() => {
+                for breakpoint in breakpoints.iter() {
+                    println!("{}", breakpoint);
+                }
+                Ok(())
+            }
+            "b" | "break" => {
+                breakpoints.insert(Breakpoint::parse(args));
+                Ok(())
+            }
+            "d" | "delete" if breakpoints.remove(&Breakpoint::parse(args)) => Ok(()),
+            "d" | "delete" => Err(verror!("No breakpoint at '{}'.", args)),
+            "p" | "state" => {
+                print_state(runner, Some(args).filter(|args| !args.is_empty()));
+                Ok(())
+            }
+            "set" => parse_set(args).and_then(|set| runner.set(&set)),
+            "j" | "jump" => runner.jump(args),
+            "branch" => match args {
+                "none" => runner.take_block(None),
+                _ => match args.parse() {
+                    Ok(block) => runner.take_block(Some(block)),
+                    Err(_) => Err(verror!("Expected a block number or `none`.")),
+                },
+            },
+            "h" | "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => Err(verror!("Unknown command '{}'. Try `help`.", command)),
+        };
+        match result {
+            Ok(()) if matches!(command, "j" | "jump" | "branch") => {
+                show_events(runner, input);
+                show_position(runner);
+            }
+            Ok(()) => show_events(runner, input),
+            Err(e) => println!("{}", format!("{}", e).red()),
+        }
+    }
+}
+
+/// Runs the story line by line, pausing at breakpoints for debugger commands.
+pub fn debug(runner: &mut Runner) {
+    let mut breakpoints = BTreeSet::new();
+    let mut input = String::new();
+    let mut paused = true;
+    println!("{}", HELP.dimmed());
+    loop {
+        show_events(runner, &mut input);
+        let (passage, line) = runner.position();
+        if breakpoints
+            .iter()
+            .any(|b: &Breakpoint| b.hit(passage, line))
+        {
+            paused = true;
+        }
+        if paused {
+            paused = match prompt(runner, &mut breakpoints, &mut input) {
+                Some(paused) => paused,
+                None => return,
+            };
+        }
+        if !runner.step() {
+            show_events(runner, &mut input);
+            println!("{}", "Story ended.".bold().cyan());
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_set() {
+        let set = parse_set("name = Alice Smith").unwrap();
+        assert_eq!(
+            set.get("name ="),
+            Some(&Value::String("Alice Smith".to_string()))
+        );
+        assert_eq!(
+            parse_set("gold += 5").unwrap().get("gold +="),
+            Some(&Value::Int(5))
+        );
+        assert!(parse_set("gold +=").is_err());
+    }
+}
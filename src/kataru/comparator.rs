@@ -1,6 +1,6 @@
 use crate::error::ValidationError;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Comparator {
     EQ,
     NEQ,
@@ -8,6 +8,8 @@ pub enum Comparator {
     GEQ,
     LT,
     LEQ,
+    /// Whether a list or set contains an item.
+    HAS,
 }
 
 impl Comparator {
@@ -19,6 +21,7 @@ impl Comparator {
             ">=" => Ok(Self::GEQ),
             "<" => Ok(Self::LT),
             "<=" => Ok(Self::LEQ),
+            "has" => Ok(Self::HAS),
             _ => Err(verror!("No valid comparator matches {}", op)),
         }
     }
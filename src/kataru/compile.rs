@@ -0,0 +1,157 @@
+use crate::error::ValidationError;
+use crate::structs::{Config, Story};
+use crate::validate::validate;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/// Starts every story in the binary format, followed by the format version.
+pub const MAGIC: &[u8] = b"KATARU";
+/// Version of the compiled formats, bumped whenever the layout of stories changes.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Formats a story can be compiled to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Compact JSON, for tools and engines that would rather not read the binary format.
+    Json,
+    /// MessagePack after a header of `MAGIC` and the format version in little endian.
+    Binary,
+}
+
+impl Format {
+    /// Picks the format from a file's extension: JSON for `.json`, binary otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::Json,
+            _ => Self::Binary,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CompiledRef<'a> {
+    version: u16,
+    config: &'a Config,
+    story: &'a Story,
+}
+
+#[derive(Deserialize)]
+struct Compiled {
+    version: u16,
+    config: Config,
+    story: Story,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u16,
+}
+
+fn check_version(version: u16) -> Result<(), ValidationError> {
+    if version != FORMAT_VERSION {
+        return Err(verror!(
+            "Story was compiled with format version {}, but version {} is expected. Compile it again.",
+            version,
+            FORMAT_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Validates a story and writes it with its config in the given format.
+pub fn compile(config: &Config, story: &Story, format: Format) -> Result<Vec<u8>, ValidationError> {
+    validate(config, story)?;
+    let compiled = CompiledRef {
+        version: FORMAT_VERSION,
+        config,
+        story,
+    };
+    match format {
+        Format::Json => serde_json::to_vec(&compiled).map_err(|e| verror!("Compile error: {}", e)),
+        Format::Binary => {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+            // Named fields keep struct variants such as `goto` and `call` apart.
+            let body =
+                rmp_serde::to_vec_named(&compiled).map_err(|e| verror!("Compile error: {}", e))?;
+            bytes.extend(body);
+            Ok(bytes)
+        }
+    }
+}
+
+/// Reads a story and its config back from either compiled format.
+pub fn decompile(bytes: &[u8]) -> Result<(Config, Story), ValidationError> {
+    let compiled: Compiled = match bytes.strip_prefix(MAGIC) {
+        Some(rest) if rest.len() >= 2 => {
+            check_version(u16::from_le_bytes([rest[0], rest[1]]))?;
+            rmp_serde::from_slice(&rest[2..]).map_err(|e| verror!("Compiled story error: {}", e))?
+        }
+        Some(_) => return Err(verror!("Compiled story error: missing format version")),
+        None => serde_json::from_slice(bytes).map_err(|e| {
+            // Report an old version rather than whatever changed in the layout.
+            match serde_json::from_slice::<Header>(bytes) {
+                Ok(header) if header.version != FORMAT_VERSION => {
+                    check_version(header.version).unwrap_err()
+                }
+                _ => verror!("Compiled story error: {}", e),
+            }
+        })?,
+    };
+    check_version(compiled.version)?;
+    Ok((compiled.config, compiled.story))
+}
+
+/// Returns whether the file at a path holds a compiled story rather than YAML.
+pub fn is_compiled(path: &Path) -> bool {
+    if Format::from_path(path) == Format::Json {
+        return true;
+    }
+    let mut magic = [0; MAGIC.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && magic == MAGIC
+}
+
+/// Loads a compiled story and its config.
+pub fn load_compiled(path: &Path) -> Result<(Config, Story), ValidationError> {
+    let bytes =
+        fs::read(path).map_err(|e| verror!("Could not read '{}': {}", path.display(), e))?;
+    decompile(&bytes).map_err(|e| verror!("File '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile() {
+        let config =
+            || -> Config { serde_yaml::from_str(include_str!("../../story/config.yml")).unwrap() };
+        let story: Story = serde_yaml::from_str(include_str!("../../story/story.yml")).unwrap();
+
+        for format in [Format::Json, Format::Binary] {
+            let bytes = compile(&config(), &story, format).unwrap();
+            let (decompiled_config, decompiled_story) = decompile(&bytes).unwrap();
+            assert_eq!(decompiled_config, config());
+            assert_eq!(decompiled_story, story);
+        }
+
+        let mut bytes = compile(&config(), &story, Format::Binary).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        bytes[MAGIC.len()] += 1;
+        assert!(decompile(&bytes).unwrap_err().message.contains("version 2"));
+        let json = String::from_utf8(compile(&config(), &story, Format::Json).unwrap()).unwrap();
+        let old = json.replacen("\"version\":1", "\"version\":0", 1);
+        assert!(decompile(old.as_bytes())
+            .unwrap_err()
+            .message
+            .contains("version 0"));
+
+        let invalid: Story = serde_yaml::from_str("Start: [{goto: Nowhere}]").unwrap();
+        assert!(compile(&config(), &invalid, Format::Json).is_err());
+    }
+}
@@ -1,86 +1,84 @@
 use crate::comparator::Comparator;
 use crate::error::ValidationError;
-use crate::structs::{Branches, Config, PassageLine, State};
+use crate::operand::{split_expression, Operand};
+use crate::structs::PassageLine;
 use crate::value::Value;
+use std::cmp::Ordering;
 
 #[derive(Debug, PartialEq)]
 pub struct Conditional<'a> {
-    pub var: &'a str,
+    pub lhs: Operand<'a>,
     pub cmp: Comparator,
     pub val: Value,
 }
 
 impl<'a> Conditional<'a> {
     pub fn parse(text: &'a str) -> Result<Self, ValidationError> {
-        let split: Vec<&'a str> = text.split(' ').collect();
+        let split = split_expression(text);
         if split.len() != 4 || split[0] != "if" {
             return Err(verror!(
-                "Conditionals must be of the form 'if VAR [<,<=,>,=>,==,] VALUE:', not {}",
+                "Conditionals must be of the form 'if VAR [<,<=,>,>=,==,!=,has] VALUE:', not {}",
                 text
             ));
         }
         Ok(Self {
-            var: split[1],
+            lhs: Operand::parse(split[1])?,
             cmp: Comparator::parse(split[2])?,
             val: Value::parse(split[3])?,
         })
     }
 
-    pub fn eval(&self, state: &State) -> Result<bool, ValidationError> {
-        self.cmp(&state[self.var])
+    pub fn compare(&self, val: &Value) -> Result<bool, ValidationError> {
+        compare(self.cmp, val, &self.val)
     }
+}
 
-    pub fn cmp(&self, val: &Value) -> Result<bool, ValidationError> {
-        if !val.same_type(&self.val) {
-            return Err(verror!(
+/// Compares a value against the value on the right hand side of a conditional.
+pub fn compare(cmp: Comparator, val: &Value, other: &Value) -> Result<bool, ValidationError> {
+    let ordering = || {
+        if val.comparable(other) {
+            Ok(val.compare(other))
+        } else {
+            Err(verror!(
                 "Comparisons require values of the same type, not {:?} and {:?}",
                 val,
-                self.val
-            ));
-        }
-        match self.cmp {
-            Comparator::EQ => Ok(val == &self.val),
-            Comparator::NEQ => Ok(val != &self.val),
-            Comparator::LT => Ok(val < &self.val),
-            Comparator::LEQ => Ok(val <= &self.val),
-            Comparator::GT => Ok(val > &self.val),
-            Comparator::GEQ => Ok(val <= &self.val),
+                other
+            ))
         }
+    };
+    match cmp {
+        Comparator::EQ => Ok(ordering()? == Some(Ordering::Equal)),
+        Comparator::NEQ => Ok(ordering()? != Some(Ordering::Equal)),
+        Comparator::LT => Ok(ordering()? == Some(Ordering::Less)),
+        Comparator::LEQ => Ok(matches!(
+            ordering()?,
+            Some(Ordering::Less | Ordering::Equal)
+        )),
+        Comparator::GT => Ok(ordering()? == Some(Ordering::Greater)),
+        Comparator::GEQ => Ok(matches!(
+            ordering()?,
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        Comparator::HAS => match val {
+            Value::List(items) => Ok(items.iter().any(|item| item.equals(other))),
+            _ => Err(verror!(
+                "Only lists and sets can contain items, not {:?}",
+                val
+            )),
+        },
     }
 }
 
 pub fn branch_len(lines: &[PassageLine]) -> usize {
     let mut length = lines.len();
     for line in lines {
-        if let PassageLine::Branches(branches) = line {
-            for (_expression, branch_lines) in branches {
-                length += branch_len(branch_lines);
-            }
+        for block in line.blocks() {
+            length += branch_len(block);
         }
     }
     length
 }
 
-/// Evaluates the conditionals in a given branch and takes the first one that evaluates to true.
-pub fn take_branch(
-    config: &mut Config,
-    branches: &Branches<PassageLine>,
-) -> Result<(), ValidationError> {
-    let mut skip_lines = 1;
-    for (expression, lines) in branches {
-        if expression == "else" {
-            continue;
-        };
-        if Conditional::parse(expression)?.eval(&config.state)? {
-            break;
-        } else {
-            skip_lines += branch_len(lines);
-        }
-    }
-    config.line += skip_lines;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -91,11 +89,11 @@ mod tests {
     fn test_value_cmp() {
         let v1 = Value::Number(1.);
         let v2 = Value::Number(2.);
-        assert_eq!(v1 < v2, true);
+        assert!(v1 < v2);
 
         let v1 = Value::Number(1.);
         let v2 = Value::String("test".to_string());
-        assert_eq!(v1 < v2, false);
+        assert_ne!(v1.partial_cmp(&v2), Some(std::cmp::Ordering::Less));
     }
 
     /// Tests construction and comparison of conditional
@@ -108,10 +106,31 @@ mod tests {
         assert_eq!(
             cond,
             Conditional {
-                var: "var",
-                val: Value::Number(5.0),
+                lhs: Operand::Var("var"),
+                val: Value::Int(5),
                 cmp: Comparator::GT
             }
         );
+
+        let cond = Conditional::parse("if chosen(Start, it's top secret) >= 1").unwrap();
+        assert_eq!(cond.lhs, Operand::Chosen("Start", "it's top secret"));
+        assert_eq!(cond.cmp, Comparator::GEQ);
+
+        // Integers and floats compare by value.
+        let cond = Conditional::parse("if var == 5.0").unwrap();
+        assert_eq!(cond.val, Value::Number(5.0));
+        assert!(cond.compare(&Value::Int(5)).unwrap());
+        assert!(Conditional::parse("if var < 5")
+            .unwrap()
+            .compare(&Value::Number(4.5))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_geq() {
+        let cond = Conditional::parse("if var >= 5").unwrap();
+        assert!(cond.compare(&Value::Number(6.)).unwrap());
+        assert!(cond.compare(&Value::Number(5.)).unwrap());
+        assert!(!cond.compare(&Value::Number(4.)).unwrap());
     }
 }
@@ -0,0 +1,258 @@
+use crate::error::ValidationError;
+use crate::operator::Operator;
+use crate::value::Value;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    String,
+    Int,
+    /// A floating point number.
+    Number,
+    Bool,
+    /// A string restricted to a declared set of variants.
+    Enum,
+    List,
+    /// A list without duplicate items.
+    Set,
+}
+
+impl ValueType {
+    /// Infers the type of a value, as used for undeclared state variables.
+    pub fn of(value: &Value) -> Option<Self> {
+        match value {
+            Value::None => None,
+            Value::String(_) => Some(Self::String),
+            Value::Int(_) => Some(Self::Int),
+            Value::Number(_) => Some(Self::Number),
+            Value::Bool(_) => Some(Self::Bool),
+            Value::List(_) => Some(Self::List),
+        }
+    }
+
+    /// Whether values of both types are stored as the same variant of `Value`.
+    pub fn stores_same(self, other: Self) -> bool {
+        let stored = |value_type| match value_type {
+            Self::Enum => Self::String,
+            Self::Set => Self::List,
+            _ => value_type,
+        };
+        stored(self) == stored(other)
+    }
+
+    /// Whether values of the other type can be assigned to variables of this type,
+    /// widening integers to floats.
+    pub fn can_hold(self, other: Self) -> bool {
+        self.stores_same(other) || (self == Self::Number && other == Self::Int)
+    }
+
+    pub fn is_collection(self) -> bool {
+        matches!(self, Self::List | Self::Set)
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Self::Int | Self::Number)
+    }
+
+    /// Whether values of this type are stored as the given value's variant.
+    /// Floats also accept integers, which are widened when assigned.
+    pub fn accepts(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (Self::String, Value::String(_))
+                | (Self::Enum, Value::String(_))
+                | (Self::Int, Value::Int(_))
+                | (Self::Number, Value::Number(_))
+                | (Self::Number, Value::Int(_))
+                | (Self::Bool, Value::Bool(_))
+                | (Self::List, Value::List(_))
+                | (Self::Set, Value::List(_))
+        )
+    }
+}
+
+/// Declares the type, default and constraints of a state variable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Declaration {
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    #[serde(default)]
+    pub default: Option<Value>,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Allowed values of an enum, or of the items of a list or set of enums.
+    #[serde(default)]
+    pub variants: Vec<String>,
+    /// Type of the items of a list or set. Items may be of any type if not given.
+    #[serde(default)]
+    pub items: Option<ValueType>,
+}
+
+impl Declaration {
+    /// Declares an integer between optional bounds.
+    pub fn int(min: Option<f64>, max: Option<f64>) -> Self {
+        Self {
+            value_type: ValueType::Int,
+            default: None,
+            min,
+            max,
+            variants: vec![],
+            items: None,
+        }
+    }
+
+    /// Returns the declaration that items of a list or set must satisfy, if any.
+    pub fn item_declaration(&self) -> Option<Self> {
+        Some(Self {
+            value_type: self.items?,
+            default: None,
+            min: None,
+            max: None,
+            variants: self.variants.clone(),
+            items: None,
+        })
+    }
+
+    /// Infers a declaration from an initial value.
+    pub fn infer(value: &Value) -> Option<Self> {
+        Some(Self {
+            value_type: ValueType::of(value)?,
+            default: Some(value.clone()),
+            min: None,
+            max: None,
+            variants: vec![],
+            items: None,
+        })
+    }
+
+    /// Returns the declared default, or the natural default of the declared type.
+    pub fn default_value(&self) -> Value {
+        if let Some(default) = &self.default {
+            return default.clone();
+        }
+        match self.value_type {
+            ValueType::String => Value::String(String::new()),
+            // Zero, clamped into the declared bounds.
+            ValueType::Int => {
+                let mut value = Value::Int(0);
+                self.clamp(&mut value);
+                value
+            }
+            ValueType::Number => {
+                let mut value = Value::Number(0.);
+                self.clamp(&mut value);
+                value
+            }
+            ValueType::Bool => Value::Bool(false),
+            ValueType::Enum => match self.variants.first() {
+                Some(variant) => Value::String(variant.clone()),
+                None => Value::None,
+            },
+            ValueType::List | ValueType::Set => Value::List(vec![]),
+        }
+    }
+
+    /// Checks that a value satisfies this declaration.
+    pub fn check(&self, value: &Value) -> Result<(), ValidationError> {
+        if !self.value_type.accepts(value) {
+            return Err(verror!(
+                "Expected a value of type {:?}, not {:?}.",
+                self.value_type,
+                value
+            ));
+        }
+        match value {
+            Value::String(variant)
+                if self.value_type == ValueType::Enum && !self.variants.contains(variant) =>
+            {
+                Err(verror!(
+                    "'{}' is not one of the variants {:?}.",
+                    variant,
+                    self.variants
+                ))
+            }
+            Value::List(items) => match self.item_declaration() {
+                Some(declaration) => items.iter().try_for_each(|item| declaration.check(item)),
+                None => Ok(()),
+            },
+            Value::Int(_) | Value::Number(_) => {
+                let n = value.as_f64().unwrap();
+                if self.min.is_some_and(|min| n < min) || self.max.is_some_and(|max| n > max) {
+                    Err(verror!(
+                        "{} is outside of the bounds [{:?}, {:?}].",
+                        n,
+                        self.min,
+                        self.max
+                    ))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Clamps a number into the declared bounds.
+    fn clamp(&self, value: &mut Value) {
+        match value {
+            Value::Int(n) => {
+                if let Some(min) = self.min {
+                    *n = (*n).max(min.ceil() as i64);
+                }
+                if let Some(max) = self.max {
+                    *n = (*n).min(max.floor() as i64);
+                }
+            }
+            Value::Number(n) => {
+                if let Some(min) = self.min {
+                    *n = n.max(min);
+                }
+                if let Some(max) = self.max {
+                    *n = n.min(max);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Coerces a value into this declaration's constraints: integers are widened for float
+    /// variables, numbers are clamped to their bounds and duplicate items are removed from sets.
+    /// Values that cannot be coerced are reported as errors.
+    pub fn enforce(&self, value: &mut Value) -> Result<(), ValidationError> {
+        if self.value_type == ValueType::Number {
+            *value = value.widened_like(&Value::Number(0.));
+        }
+        self.clamp(value);
+        match value {
+            Value::List(items) if self.value_type == ValueType::Set => {
+                let mut unique: Vec<Value> = vec![];
+                for item in items.drain(..) {
+                    if !unique.iter().any(|other| other.equals(&item)) {
+                        unique.push(item);
+                    }
+                }
+                *items = unique;
+            }
+            _ => (),
+        }
+        self.check(value)
+    }
+
+    /// Applies an operator to a copy of a variable's value and enforces this declaration
+    /// on the result, so that the variable is only changed if the result is valid.
+    pub fn apply(
+        &self,
+        op: Operator,
+        state_value: &mut Value,
+        value: &Value,
+    ) -> Result<(), ValidationError> {
+        let mut updated = state_value.clone();
+        op.apply(&mut updated, value);
+        self.enforce(&mut updated)?;
+        *state_value = updated;
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use crate::structs::{Command, Speech};
+use crate::value::Value;
+
+/// Text shown to the player, with the ID of its line if it has one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub text: String,
+    pub id: Option<String>,
+}
+
+/// Something that happens in a story, as reported by `Runner::advance`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Narration to display.
+    Text(Line),
+    /// A line spoken by a character.
+    Dialogue { character: String, speech: Speech },
+    /// Choices to pick from with `Runner::choose`, by index.
+    /// Until a choice is made, advancing presents the same choices again.
+    Choices(Vec<Line>),
+    /// A command for the host that has no registered handler.
+    Command(Command),
+    /// A state variable was given a new value.
+    StateChanged { var: String, value: Value },
+    /// The story moved into a passage, by a jump, a call or a return.
+    PassageEntered(String),
+    /// A line could not run, such as a jump to a missing passage, and was skipped.
+    Error(String),
+    /// The story is over. Advancing any further ends it again.
+    End,
+}
@@ -0,0 +1,15 @@
+use crate::structs::Speech;
+use serde::{Deserialize, Serialize};
+
+/// A line shown to the player, kept so that it can be read again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Record {
+    Text(String),
+    Dialogue {
+        character: String,
+        speech: Speech,
+    },
+    /// The text of a choice the player made.
+    Choice(String),
+}
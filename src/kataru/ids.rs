@@ -0,0 +1,375 @@
+use crate::error::ValidationError;
+use crate::loader::qualify;
+use crate::locale::{split_id, ID_MARKER};
+use crate::structs::{Config, Map};
+use std::collections::BTreeSet;
+use yaml_rust::parser::{Event as YamlEvent, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+/// A node of a YAML document, remembering where scalars start in the source.
+enum Node {
+    Scalar {
+        value: String,
+        style: TScalarStyle,
+        mark: Marker,
+    },
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+    Alias,
+}
+
+impl Node {
+    /// Returns the value of a scalar that reads as a string rather than a number,
+    /// boolean or null.
+    fn text(&self) -> Option<&str> {
+        match self {
+            Self::Scalar { value, style, .. } => {
+                let typed = *style == TScalarStyle::Plain
+                    && (matches!(value.as_str(), "" | "~" | "null" | "true" | "false")
+                        || value.parse::<f64>().is_ok());
+                if typed {
+                    None
+                } else {
+                    Some(value)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a key of a mapping.
+    fn get(&self, key: &str) -> Option<&Node> {
+        match self {
+            Self::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| k.text() == Some(key))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+/// Builds a tree of nodes from the events of the YAML parser.
+#[derive(Default)]
+struct Builder {
+    /// Items of the sequences and mappings being built, and whether each is a mapping.
+    stack: Vec<(bool, Vec<Node>)>,
+    root: Option<Node>,
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, event: YamlEvent, mark: Marker) {
+        let node = match event {
+            YamlEvent::SequenceStart(_) => return self.stack.push((false, vec![])),
+            YamlEvent::MappingStart(_) => return self.stack.push((true, vec![])),
+            YamlEvent::SequenceEnd | YamlEvent::MappingEnd => match self.stack.pop() {
+                Some((true, items)) => {
+                    let mut items = items.into_iter();
+                    let mut entries = vec![];
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        entries.push((key, value));
+                    }
+                    Node::Map(entries)
+                }
+                Some((false, items)) => Node::Seq(items),
+                None => return,
+            },
+            YamlEvent::Scalar(value, style, ..) => Node::Scalar { value, style, mark },
+            YamlEvent::Alias(_) => Node::Alias,
+            _ => return,
+        };
+        match self.stack.last_mut() {
+            Some((_, items)) => items.push(node),
+            None => self.root = Some(node),
+        }
+    }
+}
+
+/// Collects the scalars of lines without IDs, with the passages they are in.
+struct Walker<'a> {
+    config: &'a Config,
+    targets: Vec<(String, &'a Node)>,
+}
+
+impl<'a> Walker<'a> {
+    fn text(&mut self, passage: &str, node: &'a Node) {
+        if let Some(value) = node.text() {
+            if split_id(value).1.is_none() {
+                self.targets.push((passage.to_string(), node));
+            }
+        }
+    }
+
+    fn lines(&mut self, passage: &str, node: &'a Node) {
+        if let Node::Seq(lines) = node {
+            for line in lines {
+                self.line(passage, line);
+            }
+        }
+    }
+
+    /// Finds the text of a line, following the shapes of `PassageLine`.
+    fn line(&mut self, passage: &str, line: &'a Node) {
+        let entries = match line {
+            Node::Map(entries) => entries,
+            _ => return self.text(passage, line),
+        };
+        for (key, value) in entries {
+            let key = match key.text() {
+                Some(key) => key,
+                None => continue,
+            };
+            match key {
+                "choices" => {
+                    if let Node::Map(choices) = value {
+                        for (text, choice) in choices {
+                            self.text(passage, text);
+                            if let Some(text) = choice.get("text") {
+                                self.text(passage, text);
+                            }
+                        }
+                    }
+                }
+                "random" => {
+                    if let Node::Seq(branches) = value {
+                        for branch in branches {
+                            self.lines(passage, branch.get("lines").unwrap_or(branch));
+                        }
+                    }
+                }
+                "goto" | "call" | "return" | "set" => (),
+                _ if key == "else" || key.starts_with("if ") => self.lines(passage, value),
+                _ if self.config.commands.contains_key(key) => (),
+                // Dialogue, either as plain text or as a speech with its own fields.
+                _ => match value.get("text") {
+                    Some(_) if value.get("id").is_some() => (),
+                    Some(text) => self.text(passage, text),
+                    None => self.text(passage, value),
+                },
+            }
+        }
+    }
+}
+
+/// Finds the byte offset at the end of a scalar's text, where its ID goes.
+fn id_offset(source: &str, start: usize, value: &str, style: TScalarStyle) -> Option<usize> {
+    let rest = &source[start..];
+    match style {
+        TScalarStyle::Plain => match rest.starts_with(value) {
+            true => Some(start + value.len()),
+            // Plain scalars folded over several lines do not appear as they are in the source.
+            false => None,
+        },
+        TScalarStyle::SingleQuoted | TScalarStyle::DoubleQuoted => {
+            let quote = if style == TScalarStyle::SingleQuoted {
+                '\''
+            } else {
+                '"'
+            };
+            let mut chars = rest.char_indices().skip(1);
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    // Single quotes escape themselves, double quotes are escaped by backslashes.
+                    '\'' if quote == '\'' && rest[i + 1..].starts_with('\'') => {
+                        chars.next();
+                    }
+                    '\\' if quote == '"' => {
+                        chars.next();
+                    }
+                    c if c == quote => return Some(start + i),
+                    _ => (),
+                }
+            }
+            None
+        }
+        TScalarStyle::Literal | TScalarStyle::Foled => {
+            // Block scalars start on their first line of content, and run on for as
+            // long as lines are blank or indented at least as much.
+            let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let indent = start - line_start;
+            let (mut offset, mut end) = (line_start, None);
+            for line in source[line_start..].split_inclusive('\n') {
+                let content = line.trim_end();
+                if !content.is_empty() {
+                    if content.len() - content.trim_start_matches(' ').len() < indent {
+                        break;
+                    }
+                    end = Some(offset + content.len());
+                }
+                offset += line.len();
+            }
+            end
+        }
+        TScalarStyle::Any => None,
+    }
+}
+
+/// Makes an ID prefix from a passage name, such as `chapter2.the_end` for `chapter2.The End`.
+fn id_prefix(passage: &str) -> String {
+    passage
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == '.' || c == '-' => c.to_ascii_lowercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Gives IDs to the lines of a story file that have none by adding them to the end
+/// of their text, editing the source in place so that its formatting and comments are
+/// kept. IDs are the passage name and a number, skipping any in `used`.
+/// Returns the new source and the number of IDs added.
+pub fn assign_ids(
+    config: &Config,
+    source: &str,
+    namespace: &str,
+    used: &mut BTreeSet<String>,
+) -> Result<(String, usize), ValidationError> {
+    let mut builder = Builder::default();
+    Parser::new(source.chars())
+        .load(&mut builder, false)
+        .map_err(|e| verror!("{}", e))?;
+    let mut walker = Walker {
+        config,
+        targets: vec![],
+    };
+    if let Some(Node::Map(passages)) = &builder.root {
+        for (name, lines) in passages {
+            if let Some(name) = name.text() {
+                walker.lines(&qualify(namespace, name), lines);
+            }
+        }
+    }
+
+    // Markers count characters, so find the byte offset of each character.
+    let offsets: Vec<usize> = source
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(Some(source.len()))
+        .collect();
+    let mut counters: Map<String, usize> = Map::new();
+    let mut insertions = vec![];
+    for (passage, node) in walker.targets {
+        let (value, style, mark) = match node {
+            Node::Scalar { value, style, mark } => (value, *style, mark),
+            _ => continue,
+        };
+        let offset = match id_offset(source, offsets[mark.index()], value, style) {
+            Some(offset) => offset,
+            None => {
+                return Err(verror!(
+                    "Line {}: cannot add an ID to '{}'; write it on one line or quote it.",
+                    mark.line(),
+                    value
+                ))
+            }
+        };
+        let prefix = id_prefix(&passage);
+        let counter = counters.entry(prefix.clone()).or_insert(0);
+        let id = loop {
+            *counter += 1;
+            let id = format!("{}.{}", prefix, counter);
+            if used.insert(id.clone()) {
+                break id;
+            }
+        };
+        let space = if value.is_empty() { "" } else { " " };
+        insertions.push((offset, format!("{}{}{}", space, ID_MARKER, id)));
+    }
+
+    let count = insertions.len();
+    let mut result = source.to_string();
+    insertions.sort_by_key(|(offset, _)| *offset);
+    for (offset, id) in insertions.into_iter().rev() {
+        result.insert_str(offset, &id);
+    }
+    Ok((result, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locale::lines;
+    use crate::runner::tests::{config_with, story};
+
+    #[test]
+    fn test_assign_ids() {
+        let config = config_with("{commands: {play_sound: [string]}}");
+        let source = r#"# The opening.
+Start:
+  - Hello there. # Greets the player.
+  - 'It''s me.'
+  - "Say \"hi\"."
+  - play_sound: door.wav
+  - shake_screen: 0.5
+  - Alice (happy): Hi! @line:hi
+  - Bob: {text: Hey!, emotion: calm}
+  - |
+    Once upon a time,
+      in a land far away.
+
+  - if visits(Start) > 1:
+      - >
+        Welcome
+        back.
+    else: [Welcome.]
+  - random:
+      - [Heads.]
+      - { weight: 2, lines: [Tails.] }
+  - choices:
+      Leave:
+        goto: The End
+        text: You leave.
+      Stay: Start
+The End:
+  - set: { gold +=: 1 }
+  - The end.
+"#;
+        let mut used = BTreeSet::new();
+        used.insert("start.2".to_string());
+        let (result, count) = assign_ids(&config, source, "", &mut used).unwrap();
+        assert_eq!(count, 13);
+        assert_eq!(
+            result,
+            r#"# The opening.
+Start:
+  - Hello there. @line:start.1 # Greets the player.
+  - 'It''s me. @line:start.3'
+  - "Say \"hi\". @line:start.4"
+  - play_sound: door.wav
+  - shake_screen: 0.5
+  - Alice (happy): Hi! @line:hi
+  - Bob: {text: Hey! @line:start.5, emotion: calm}
+  - |
+    Once upon a time,
+      in a land far away. @line:start.6
+
+  - if visits(Start) > 1:
+      - >
+        Welcome
+        back. @line:start.7
+    else: [Welcome. @line:start.8]
+  - random:
+      - [Heads. @line:start.9]
+      - { weight: 2, lines: [Tails. @line:start.10] }
+  - choices:
+      Leave @line:start.11:
+        goto: The End
+        text: You leave. @line:start.12
+      Stay @line:start.13: Start
+The End:
+  - set: { gold +=: 1 }
+  - The end. @line:the_end.1
+"#
+        );
+
+        // Every line now has an ID, so running again changes nothing.
+        let story = story(&result);
+        assert!(lines(&config, &story).iter().all(|line| line.id.is_some()));
+        let (again, count) = assign_ids(&config, &result, "", &mut used).unwrap();
+        assert_eq!((again.as_str(), count), (result.as_str(), 0));
+
+        let folded = "Start:\n  - A plain line\n    folded in two.\n";
+        assert!(assign_ids(&config, folded, "", &mut used).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::error::ValidationError;
+
+/// A piece of text to be interpolated.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Piece<'a> {
+    Literal(&'a str),
+    /// A `{expression}` placeholder, such as `{gold}` or `{len(inventory)}`.
+    Expression(&'a str),
+}
+
+/// Splits text into literals and placeholders. `{{` and `}}` escape literal braces.
+pub(crate) fn pieces(text: &str) -> Result<Vec<Piece<'_>>, ValidationError> {
+    let mut pieces = vec![];
+    let mut rest = text;
+    while let Some(i) = rest.find(['{', '}']) {
+        let (literal, tail) = rest.split_at(i);
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            pieces.push(Piece::Literal(&tail[..1]));
+            rest = &tail[2..];
+        } else if tail.starts_with('}') {
+            return Err(verror!("Unmatched '}}' in text '{}'.", text));
+        } else {
+            match tail.find('}') {
+                Some(end) => {
+                    pieces.push(Piece::Expression(tail[1..end].trim()));
+                    rest = &tail[end + 1..];
+                }
+                None => return Err(verror!("Unclosed '{{' in text '{}'.", text)),
+            }
+        }
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Literal(rest));
+    }
+    Ok(pieces)
+}
+
+/// Returns the expressions interpolated in the given text.
+pub fn expressions(text: &str) -> Result<Vec<&str>, ValidationError> {
+    Ok(pieces(text)?
+        .into_iter()
+        .filter_map(|piece| match piece {
+            Piece::Expression(expression) => Some(expression),
+            Piece::Literal(_) => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pieces() {
+        assert_eq!(
+            pieces("{gold} gold, {{{ len(inventory) }}} items").unwrap(),
+            vec![
+                Piece::Expression("gold"),
+                Piece::Literal(" gold, "),
+                Piece::Literal("{"),
+                Piece::Expression("len(inventory)"),
+                Piece::Literal("}"),
+                Piece::Literal(" items"),
+            ]
+        );
+        assert!(pieces("{gold").is_err());
+        assert!(pieces("gold}").is_err());
+    }
+}
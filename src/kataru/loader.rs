@@ -0,0 +1,369 @@
+use crate::error::ValidationError;
+use crate::operand::{is_call, rename_passages};
+#[cfg(feature = "yaml")]
+use crate::structs::Config;
+use crate::structs::{Choice, Map, Passage, PassageLine, RandomBranch, State, Story};
+use crate::value::Value;
+#[cfg(feature = "yaml")]
+use serde::Deserialize;
+#[cfg(feature = "yaml")]
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Separates namespaces from passage names, as in `chapter2.Intro`.
+pub const NAMESPACE_SEPARATOR: char = '.';
+
+/// A story file to include from a manifest, optionally under an explicit namespace.
+#[cfg(feature = "yaml")]
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Include {
+    Path(PathBuf),
+    Namespaced { path: PathBuf, namespace: String },
+}
+
+/// Lists the story files that make up a story.
+/// Unless given explicitly, each file's namespace is its path without the extension.
+#[cfg(feature = "yaml")]
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub include: Vec<Include>,
+}
+
+/// A parsed story file whose passage names have not yet been qualified.
+pub struct StoryFile {
+    pub path: PathBuf,
+    pub namespace: String,
+    pub story: Story,
+}
+
+/// Returns the fully qualified name of a passage in a namespace.
+pub fn qualify(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, name)
+    }
+}
+
+/// Derives a namespace from a path relative to the story root, e.g. `chapter2/intro.yml`
+/// becomes `chapter2.intro`.
+#[cfg(feature = "yaml")]
+fn path_namespace(relative: &Path) -> String {
+    relative
+        .with_extension("")
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(&NAMESPACE_SEPARATOR.to_string())
+}
+
+#[cfg(feature = "yaml")]
+fn read(path: &Path) -> Result<String, ValidationError> {
+    fs::read_to_string(path).map_err(|e| verror!("Could not read '{}': {}", path.display(), e))
+}
+
+#[cfg(feature = "yaml")]
+fn parse_story(path: &Path, text: &str) -> Result<Story, ValidationError> {
+    serde_yaml::from_str(text).map_err(|e| verror!("File '{}': {}", path.display(), e))
+}
+
+/// Returns the namespace of a file found in a story directory, from its path relative to
+/// the directory. Files at the top of the directory share the root namespace, so that the
+/// config can name their passages directly.
+#[cfg(feature = "yaml")]
+fn dir_namespace(relative: &Path) -> String {
+    match relative.parent() {
+        Some(parent) if parent != Path::new("") => path_namespace(relative),
+        _ => String::new(),
+    }
+}
+
+/// Returns the name of a YAML file without its extension, or none for other files.
+#[cfg(feature = "yaml")]
+fn yaml_stem(path: &Path) -> Option<&str> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yml") | Some("yaml") => path.file_stem().and_then(|stem| stem.to_str()),
+        _ => None,
+    }
+}
+
+/// Returns whether a file is a manifest, which must be named `manifest.yml`.
+#[cfg(feature = "yaml")]
+fn is_manifest(path: &Path) -> bool {
+    yaml_stem(path) == Some("manifest")
+}
+
+/// Returns whether a file in a story directory is a story file.
+/// YAML files named `config` or `manifest` are skipped, so the config can live beside
+/// the story.
+#[cfg(feature = "yaml")]
+fn is_story_file(path: &Path) -> bool {
+    !matches!(yaml_stem(path), None | Some("config") | Some("manifest"))
+}
+
+/// Recursively collects the story files in a directory, in a stable order.
+#[cfg(feature = "yaml")]
+fn collect_dir(root: &Path, dir: &Path, files: &mut Vec<StoryFile>) -> Result<(), ValidationError> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| verror!("Could not read '{}': {}", dir.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            collect_dir(root, &path, files)?;
+        } else if is_story_file(&path) {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            files.push(StoryFile {
+                namespace: dir_namespace(relative),
+                story: parse_story(&path, &read(&path)?)?,
+                path,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Collects the story files listed in a manifest, relative to the manifest's directory.
+#[cfg(feature = "yaml")]
+fn collect_manifest(
+    root: &Path,
+    manifest: Manifest,
+    files: &mut Vec<StoryFile>,
+) -> Result<(), ValidationError> {
+    for include in manifest.include {
+        let (relative, namespace) = match include {
+            Include::Path(path) => {
+                let namespace = path_namespace(&path);
+                (path, namespace)
+            }
+            Include::Namespaced { path, namespace } => (path, namespace),
+        };
+        let path = root.join(relative);
+        files.push(StoryFile {
+            namespace,
+            story: parse_story(&path, &read(&path)?)?,
+            path,
+        });
+    }
+    Ok(())
+}
+
+/// Returns the story files making up the story at a path.
+/// The path may be a directory of story files, a manifest named `manifest.yml`,
+/// or a single story file.
+/// Files in subdirectories of a story directory are namespaced by their path.
+#[cfg(feature = "yaml")]
+pub fn story_files(path: &Path) -> Result<Vec<StoryFile>, ValidationError> {
+    let mut files = vec![];
+    if path.is_dir() {
+        collect_dir(path, path, &mut files)?;
+        return Ok(files);
+    }
+    let text = read(path)?;
+    if is_manifest(path) {
+        let manifest: Manifest = serde_yaml::from_str(&text)
+            .map_err(|e| verror!("Manifest '{}': {}", path.display(), e))?;
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        collect_manifest(root, manifest, &mut files)?;
+    } else {
+        files.push(StoryFile {
+            namespace: String::new(),
+            story: parse_story(path, &text)?,
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(files)
+}
+
+/// Resolves a passage reference made from inside a namespace.
+/// The innermost enclosing namespace defining the passage wins.
+/// Unknown passages are left as they are for validation to report.
+pub fn resolve(story: &Story, namespace: &str, name: &str) -> String {
+    let mut namespace = namespace;
+    loop {
+        let qualified = qualify(namespace, name);
+        if story.contains_key(&qualified) {
+            return qualified;
+        }
+        if namespace.is_empty() {
+            return name.to_string();
+        }
+        namespace = match namespace.rfind(NAMESPACE_SEPARATOR) {
+            Some(i) => &namespace[..i],
+            None => "",
+        };
+    }
+}
+
+fn resolve_state(state: &mut State, resolve: &dyn Fn(&str) -> String) {
+    for value in state.values_mut() {
+        if let Value::String(text) = value {
+            if is_call(text) {
+                *text = rename_passages(text, resolve);
+            }
+        }
+    }
+}
+
+fn resolve_lines(lines: &mut Passage, resolve: &dyn Fn(&str) -> String) {
+    for line in lines {
+        match line {
+            PassageLine::Branches(branches) => {
+                *branches = branches
+                    .drain()
+                    .map(|(expression, mut branch_lines)| {
+                        resolve_lines(&mut branch_lines, resolve);
+                        (rename_passages(&expression, resolve), branch_lines)
+                    })
+                    .collect();
+            }
+            PassageLine::Random(random) => {
+                for branch in &mut random.random {
+                    match branch {
+                        RandomBranch::Lines(lines) => resolve_lines(lines, resolve),
+                        RandomBranch::Weighted { lines, .. } => resolve_lines(lines, resolve),
+                    }
+                }
+            }
+            PassageLine::Choices(choices) => {
+                for choice in choices.choices.values_mut() {
+                    match choice {
+                        Choice::PassageName(passage) => *passage = resolve(passage),
+                        Choice::ChoiceCmd(cmd) => {
+                            cmd.goto = resolve(&cmd.goto);
+                            resolve_state(&mut cmd.set, resolve);
+                        }
+                    }
+                }
+            }
+            PassageLine::Goto(goto) => goto.goto = resolve(&goto.goto),
+            PassageLine::Call(call) => call.call = resolve(&call.call),
+            PassageLine::SetCmd(cmd) => resolve_state(&mut cmd.set, resolve),
+            _ => (),
+        }
+    }
+}
+
+/// Merges story files into one story, qualifying passage names with their file's namespace
+/// and resolving references between passages relative to the referencing file.
+pub fn merge_story_files(files: Vec<StoryFile>) -> Result<Story, ValidationError> {
+    let mut story = Story::new();
+    let mut sources: Map<String, &Path> = Map::new();
+    for file in &files {
+        for (name, passage) in &file.story {
+            let qualified = qualify(&file.namespace, name);
+            if let Some(other) = sources.get(&qualified) {
+                return Err(verror!(
+                    "Passage '{}' is defined in both '{}' and '{}'.",
+                    qualified,
+                    other.display(),
+                    file.path.display()
+                ));
+            }
+            sources.insert(qualified.clone(), &file.path);
+            story.insert(qualified, passage.clone());
+        }
+    }
+    let names = story.clone();
+    for file in &files {
+        let resolve = |name: &str| resolve(&names, &file.namespace, name);
+        for name in file.story.keys() {
+            let passage = story.get_mut(&qualify(&file.namespace, name)).unwrap();
+            resolve_lines(passage, &resolve);
+        }
+    }
+    Ok(story)
+}
+
+/// Loads a story from a directory of story files, a manifest, or a single story file.
+#[cfg(feature = "yaml")]
+pub fn load_story(path: &Path) -> Result<Story, ValidationError> {
+    merge_story_files(story_files(path)?)
+}
+
+/// Reads a config from a YAML file.
+#[cfg(feature = "yaml")]
+pub fn load_config(path: &Path) -> Result<Config, ValidationError> {
+    serde_yaml::from_str(&read(path)?).map_err(|e| verror!("File '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(namespace: &str, text: &str) -> StoryFile {
+        StoryFile {
+            path: PathBuf::from(format!("{}.yml", namespace)),
+            namespace: namespace.to_string(),
+            story: serde_yaml::from_str(text).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_merge_story_files() {
+        let story = merge_story_files(vec![
+            file("", "{Start: [{goto: Intro}], Intro: [Root intro.]}"),
+            file(
+                "chapter2",
+                "{Intro: [{if visits(Intro) > 0: [{goto: Start}]}, {goto: Outro}], Outro: [{call: Intro}]}",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(
+            story.keys().collect::<Vec<_>>(),
+            vec!["Intro", "Start", "chapter2.Intro", "chapter2.Outro"]
+        );
+        assert_eq!(
+            story["chapter2.Intro"],
+            serde_yaml::from_str::<Passage>(
+                "[{if visits(chapter2.Intro) > 0: [{goto: Start}]}, {goto: chapter2.Outro}]"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            story["chapter2.Outro"],
+            serde_yaml::from_str::<Passage>("[{call: chapter2.Intro}]").unwrap()
+        );
+
+        let duplicate = merge_story_files(vec![
+            file("", "{Start: [Hello.]}"),
+            StoryFile {
+                path: PathBuf::from("other.yml"),
+                ..file("", "{Start: [Hi.]}")
+            },
+        ]);
+        assert!(duplicate.is_err());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_manifest() {
+        assert!(is_manifest(Path::new("game/manifest.yml")));
+        assert!(!is_manifest(Path::new("game/story.yml")));
+        assert!(!is_story_file(Path::new("game/manifest.yaml")));
+        assert!(is_story_file(Path::new("game/intro.yml")));
+
+        // Mistakes in a manifest are reported instead of reading it as a story file.
+        let dir = std::env::temp_dir().join("kataru-test-manifest");
+        fs::create_dir_all(&dir).unwrap();
+        let manifest = dir.join("manifest.yml");
+        fs::write(&manifest, "includes: [intro.yml]").unwrap();
+        let error = story_files(&manifest).err().unwrap();
+        assert!(error.message.starts_with("Manifest"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_dir_namespace() {
+        assert_eq!(dir_namespace(Path::new("story.yml")), "");
+        assert_eq!(
+            dir_namespace(Path::new("chapter2/intro.yml")),
+            "chapter2.intro"
+        );
+    }
+}
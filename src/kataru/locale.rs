@@ -0,0 +1,265 @@
+#[cfg(feature = "locale")]
+use crate::error::ValidationError;
+use crate::structs::{choice_key, Choice, Command, Config, Map, PassageLine, Story};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "locale")]
+use std::fs;
+#[cfg(feature = "locale")]
+use std::path::Path;
+
+/// Marks the ID of a line at the end of its text, as in `Hello! @line:greeting`.
+pub const ID_MARKER: &str = "@line:";
+
+/// Splits the ID off the end of a line's text, returning the text without it.
+pub fn split_id(text: &str) -> (&str, Option<&str>) {
+    // Block scalars end in a newline after the ID.
+    let trimmed = text.trim_end();
+    if let Some(start) = trimmed.rfind(ID_MARKER) {
+        let (source, id) = (&trimmed[..start], &trimmed[start + ID_MARKER.len()..]);
+        let valid = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.');
+        if valid && (source.is_empty() || source.ends_with(char::is_whitespace)) {
+            return (source.trim_end(), Some(id));
+        }
+    }
+    (text, None)
+}
+
+/// Returns the key identifying a line: its ID, or its text if it has none.
+pub fn line_key(text: &str) -> &str {
+    match split_id(text) {
+        (_, Some(id)) => id,
+        (source, None) => source,
+    }
+}
+
+/// A line of the story shown to the player, and so open to translation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoryLine<'s> {
+    pub passage: &'s str,
+    pub id: Option<&'s str>,
+    /// Text of the line without its ID.
+    pub source: &'s str,
+}
+
+fn collect_lines<'s>(
+    config: &Config,
+    passage: &'s str,
+    lines: &'s [PassageLine],
+    result: &mut Vec<StoryLine<'s>>,
+) {
+    let push = |result: &mut Vec<StoryLine<'s>>, text: &'s str, id: Option<&'s str>| {
+        let (source, own_id) = split_id(text);
+        result.push(StoryLine {
+            passage,
+            id: id.or(own_id),
+            source,
+        });
+    };
+    for line in lines {
+        match line {
+            PassageLine::Text(text) => push(result, text, None),
+            PassageLine::Dialogue(dialogue)
+                if Command::from_dialogue(dialogue, |name| config.commands.contains_key(name))
+                    .is_none() =>
+            {
+                dialogue
+                    .values()
+                    .for_each(|text| push(result, text.text(), text.id()))
+            }
+            PassageLine::Choices(choices) => {
+                for (text, choice) in &choices.choices {
+                    push(result, text, None);
+                    if let Choice::ChoiceCmd(cmd) = choice {
+                        cmd.text.iter().for_each(|text| push(result, text, None));
+                    }
+                }
+            }
+            _ => (),
+        }
+        for block in line.blocks() {
+            collect_lines(config, passage, block, result);
+        }
+    }
+}
+
+/// Collects every line of text, dialogue and choice in the story, in order.
+pub fn lines<'s>(config: &Config, story: &'s Story) -> Vec<StoryLine<'s>> {
+    let mut result = vec![];
+    for (passage, lines) in story {
+        collect_lines(config, passage, lines, &mut result);
+    }
+    result
+}
+
+/// Moves the counts of choices saved under their text to their IDs, for saves made
+/// before the choices were given IDs.
+pub fn migrate_chosen(config: &mut Config, story: &Story) {
+    for line in lines(config, story) {
+        if let Some(id) = line.id {
+            if let Some(count) = config.chosen.remove(&choice_key(line.passage, line.source)) {
+                *config
+                    .chosen
+                    .entry(choice_key(line.passage, id))
+                    .or_insert(0) += count;
+            }
+        }
+    }
+}
+
+/// A row of a translation table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Translation {
+    pub id: String,
+    /// Text of the line when it was translated, to tell when the translation is stale.
+    pub source: String,
+    /// Translated text, or empty if the line is not translated yet.
+    #[serde(default)]
+    pub translation: String,
+}
+
+/// Translations of the lines of a story into one language, by line ID.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Locale {
+    pub translations: Map<String, Translation>,
+}
+
+impl Locale {
+    /// Parses a table with `id`, `source` and `translation` columns.
+    #[cfg(feature = "locale")]
+    pub fn from_csv(text: &str) -> Result<Self, ValidationError> {
+        let mut translations = Map::new();
+        for row in csv::Reader::from_reader(text.as_bytes()).deserialize() {
+            let row: Translation = row.map_err(|e| verror!("Translation error: {}", e))?;
+            translations.insert(row.id.clone(), row);
+        }
+        Ok(Self { translations })
+    }
+
+    /// Writes the table with `id`, `source` and `translation` columns, ordered by ID.
+    #[cfg(feature = "locale")]
+    pub fn to_csv(&self) -> Result<String, ValidationError> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for row in self.translations.values() {
+            writer
+                .serialize(row)
+                .map_err(|e| verror!("Translation error: {}", e))?;
+        }
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| verror!("Translation error: {}", e))?;
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    /// Builds the table of the lines in a story that have IDs. Rows of `old` for lines
+    /// still in the story are kept as they are, so that stale translations stay visible.
+    pub fn extract(config: &Config, story: &Story, old: Option<&Locale>) -> Self {
+        let mut translations = Map::new();
+        for line in lines(config, story) {
+            if let Some(id) = line.id {
+                let row = old
+                    .and_then(|old| old.translations.get(id))
+                    .cloned()
+                    .unwrap_or_else(|| Translation {
+                        id: id.to_string(),
+                        source: line.source.to_string(),
+                        translation: String::new(),
+                    });
+                translations.insert(id.to_string(), row);
+            }
+        }
+        Self { translations }
+    }
+
+    /// Returns the translation of the line with the given ID, if it is translated.
+    pub fn get(&self, id: &str) -> Option<&str> {
+        match self.translations.get(id) {
+            Some(row) if !row.translation.is_empty() => Some(&row.translation),
+            _ => None,
+        }
+    }
+
+    /// Returns the text to show for a line: its translation if it has one,
+    /// or else its text without the ID.
+    pub fn translate<'a>(&'a self, text: &'a str) -> &'a str {
+        let (source, id) = split_id(text);
+        id.and_then(|id| self.get(id)).unwrap_or(source)
+    }
+}
+
+/// Reads a translation table from a CSV file.
+#[cfg(feature = "locale")]
+pub fn load_locale(path: &Path) -> Result<Locale, ValidationError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| verror!("Could not read '{}': {}", path.display(), e))?;
+    Locale::from_csv(&text).map_err(|e| verror!("File '{}': {}", path.display(), e))
+}
+
+#[cfg(all(test, feature = "locale"))]
+mod tests {
+    use super::*;
+    use crate::runner::tests::{config_with, story};
+
+    #[test]
+    fn test_locale() {
+        assert_eq!(split_id("Hi! @line:hi_1"), ("Hi!", Some("hi_1")));
+        assert_eq!(split_id("@line:hi"), ("", Some("hi")));
+        assert_eq!(split_id("Hi,\nyou! @line:hi\n"), ("Hi,\nyou!", Some("hi")));
+        assert_eq!(split_id("Mail me@line:hi"), ("Mail me@line:hi", None));
+        assert_eq!(split_id("Hi! @line:"), ("Hi! @line:", None));
+        assert_eq!(line_key("Go left @line:left"), "left");
+        assert_eq!(line_key("Go left"), "Go left");
+
+        let config = config_with("{commands: {play_sound: [string]}}");
+        let tagged = story(
+            r#"
+Start:
+  - Hello. @line:hello
+  - play_sound: door.wav
+  - Alice: Hi! @line:hi
+  - if true:
+      - Nested.
+  - choices:
+      Leave @line:leave: End
+End: []
+"#,
+        );
+        let sources: Vec<(Option<&str>, &str)> = lines(&config, &tagged)
+            .iter()
+            .map(|line| (line.id, line.source))
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                (Some("hello"), "Hello."),
+                (Some("hi"), "Hi!"),
+                (None, "Nested."),
+                (Some("leave"), "Leave")
+            ]
+        );
+
+        let mut locale = Locale::extract(&config, &tagged, None);
+        assert_eq!(locale.translations.len(), 3);
+        locale.translations.get_mut("hi").unwrap().translation = "Salut, \"toi\" !".into();
+        let locale = Locale::from_csv(&locale.to_csv().unwrap()).unwrap();
+        assert_eq!(locale.translate("Hi! @line:hi"), "Salut, \"toi\" !");
+        assert_eq!(locale.translate("Hello. @line:hello"), "Hello.");
+        assert_eq!(locale.translate("Nested."), "Nested.");
+
+        // Counts saved before a choice had an ID move to the ID.
+        let mut saved = config_with("{chosen: {Start/Leave: 2}}");
+        migrate_chosen(&mut saved, &tagged);
+        assert_eq!(
+            saved.chosen.into_iter().collect::<Vec<_>>(),
+            vec![("Start/leave".to_string(), 2)]
+        );
+
+        // Existing rows are kept, so edited sources can be told apart.
+        let edited = story("Start: [Hey! @line:hi]");
+        let locale = Locale::extract(&config, &edited, Some(&locale));
+        assert_eq!(locale.translations.len(), 1);
+        assert_eq!(locale.translations["hi"].source, "Hi!");
+    }
+}
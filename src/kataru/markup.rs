@@ -0,0 +1,100 @@
+/// A run of text inside the same markup tags, outermost tag first.
+#[derive(Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+/// Returns the name of a tag such as `b` or `bright blue`, and whether it closes.
+fn parse_tag(inner: &str) -> Option<(&str, bool)> {
+    let (name, closing) = match inner.strip_prefix('/') {
+        Some(name) => (name, true),
+        None => (inner, false),
+    };
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == ' ' || c == '_' || c == '-');
+    if valid {
+        Some((name, closing))
+    } else {
+        None
+    }
+}
+
+/// Splits text with markup such as `<b>bold</b>` or `<blue>blue</blue>` into spans.
+/// A closing tag closes the innermost open tag of the same name.
+/// Anything that is not a tag, like a lone `<`, is kept as text.
+pub fn spans(text: &str) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut tags: Vec<String> = vec![];
+    let mut current = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        let tag = rest[open + 1..]
+            .find('>')
+            .and_then(|close| parse_tag(&rest[open + 1..open + 1 + close]).map(|tag| (close, tag)));
+        let (close, (name, closing)) = match tag {
+            Some(tag) => tag,
+            None => {
+                current.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+                continue;
+            }
+        };
+        current.push_str(&rest[..open]);
+        if !current.is_empty() {
+            spans.push(Span {
+                text: std::mem::take(&mut current),
+                tags: tags.clone(),
+            });
+        }
+        if !closing {
+            tags.push(name.to_string());
+        } else if let Some(i) = tags.iter().rposition(|tag| tag == name) {
+            tags.remove(i);
+        }
+        rest = &rest[open + close + 2..];
+    }
+    current.push_str(rest);
+    if !current.is_empty() {
+        spans.push(Span {
+            text: current,
+            tags,
+        });
+    }
+    spans
+}
+
+/// Removes markup tags, leaving the text players read.
+pub fn plain(text: &str) -> String {
+    spans(text).into_iter().map(|span| span.text).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans() {
+        let span = |text: &str, tags: &[&str]| Span {
+            text: text.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        };
+        assert_eq!(
+            spans("<blue>What</blue> are <b>you <i>doing</i></b>?"),
+            vec![
+                span("What", &["blue"]),
+                span(" are ", &[]),
+                span("you ", &["b"]),
+                span("doing", &["b", "i"]),
+                span("?", &[]),
+            ]
+        );
+        assert_eq!(spans("1 < 2 <3"), vec![span("1 < 2 <3", &[])]);
+        assert_eq!(
+            plain("<bright blue>Hi</bright blue>, <i>you</i>."),
+            "Hi, you."
+        );
+    }
+}
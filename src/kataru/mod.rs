@@ -1,15 +1,43 @@
 #[macro_use]
 pub mod error;
 pub mod comparator;
+#[cfg(feature = "compiled")]
+pub mod compile;
 pub mod conditional;
+pub mod declaration;
+pub mod event;
+pub mod history;
+#[cfg(feature = "yaml")]
+pub mod ids;
+pub mod interpolate;
+pub mod loader;
+pub mod locale;
+pub mod markup;
+pub mod observer;
+pub mod operand;
 pub mod operator;
+pub mod program;
+pub mod reload;
+pub mod rng;
 pub mod runner;
 pub mod state;
 pub mod structs;
 pub mod validate;
 pub mod value;
 
+#[cfg(feature = "compiled")]
+pub use compile::{compile, load_compiled, Format};
 pub use error::ValidationError;
+pub use event::{Event, Line};
+pub use history::Record;
+#[cfg(feature = "yaml")]
+pub use loader::{load_config, load_story};
+#[cfg(feature = "locale")]
+pub use locale::load_locale;
+pub use locale::Locale;
+pub use observer::Observer;
+pub use reload::reload;
+pub use rng::Rng;
 pub use runner::Runner;
-pub use structs::{Config, Passage, PassageLine, Story};
-pub use validate::validate;
+pub use structs::{CharacterData, Config, Passage, PassageLine, Story};
+pub use validate::{validate, validate_assets, validate_locale};
@@ -0,0 +1,34 @@
+use crate::value::Value;
+
+/// Receives callbacks as a story runs, such as for analytics or achievements.
+/// Every callback does nothing by default.
+pub trait Observer {
+    fn passage_entered(&mut self, _passage: &str) {}
+    fn passage_exited(&mut self, _passage: &str) {}
+    fn state_changed(&mut self, _var: &str, _old: &Value, _new: &Value) {}
+    fn choice_made(&mut self, _passage: &str, _choice: &str) {}
+    fn story_ended(&mut self) {}
+}
+
+/// Lets callers keep ownership of an observer and inspect it after the story runs.
+impl<T: Observer + ?Sized> Observer for &mut T {
+    fn passage_entered(&mut self, passage: &str) {
+        (**self).passage_entered(passage)
+    }
+
+    fn passage_exited(&mut self, passage: &str) {
+        (**self).passage_exited(passage)
+    }
+
+    fn state_changed(&mut self, var: &str, old: &Value, new: &Value) {
+        (**self).state_changed(var, old, new)
+    }
+
+    fn choice_made(&mut self, passage: &str, choice: &str) {
+        (**self).choice_made(passage, choice)
+    }
+
+    fn story_ended(&mut self) {
+        (**self).story_ended()
+    }
+}
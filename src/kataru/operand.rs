@@ -0,0 +1,105 @@
+use crate::error::ValidationError;
+
+/// The left hand side of a conditional: either a state variable or a builtin function.
+#[derive(Debug, PartialEq)]
+pub enum Operand<'a> {
+    Var(&'a str),
+    /// `visits(Passage)`: number of times a passage has been entered.
+    Visits(&'a str),
+    /// `chosen(Passage, choice)`: number of times a choice in a passage has been made.
+    Chosen(&'a str, &'a str),
+    /// `rand(min, max)`: a random integer between min and max inclusive.
+    Rand(i64, i64),
+    /// `len(var)`: number of items in a list or set.
+    Len(&'a str),
+}
+
+/// Splits an expression on spaces, keeping parenthesized arguments together.
+pub fn split_expression(text: &str) -> Vec<&str> {
+    let mut split = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ' ' if depth == 0 => {
+                if start < i {
+                    split.push(&text[start..i]);
+                }
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if start < text.len() {
+        split.push(&text[start..]);
+    }
+    split
+}
+
+/// Returns true if the text is a function call of the form `name(arg1, arg2, ...)`.
+pub fn is_call(text: &str) -> bool {
+    parse_call(text).is_some()
+}
+
+fn parse_int(arg: &str) -> Result<i64, ValidationError> {
+    arg.parse()
+        .map_err(|_| verror!("Expected an integer argument, not '{}'.", arg))
+}
+
+/// Parses a function call of the form `name(arg1, arg2, ...)`.
+fn parse_call(text: &str) -> Option<(&str, Vec<&str>)> {
+    let open = text.find('(')?;
+    if !text.ends_with(')') {
+        return None;
+    }
+    let args = &text[open + 1..text.len() - 1];
+    let args = if args.trim().is_empty() {
+        vec![]
+    } else {
+        args.split(',').map(str::trim).collect()
+    };
+    Some((&text[..open], args))
+}
+
+/// Rewrites the passage names passed to builtin functions in an expression.
+pub fn rename_passages(text: &str, rename: &dyn Fn(&str) -> String) -> String {
+    split_expression(text)
+        .into_iter()
+        .map(|token| match parse_call(token) {
+            Some((name, mut args))
+                if (name == "visits" || name == "chosen") && !args.is_empty() =>
+            {
+                let passage = rename(args[0]);
+                args[0] = &passage;
+                format!("{}({})", name, args.join(", "))
+            }
+            _ => token.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl<'a> Operand<'a> {
+    pub fn parse(text: &'a str) -> Result<Self, ValidationError> {
+        match parse_call(text) {
+            None => Ok(Self::Var(text)),
+            Some(("visits", args)) if args.len() == 1 => Ok(Self::Visits(args[0])),
+            Some(("chosen", args)) if args.len() == 2 => Ok(Self::Chosen(args[0], args[1])),
+            Some(("len", args)) if args.len() == 1 => Ok(Self::Len(args[0])),
+            Some(("rand", args)) if args.len() == 2 => {
+                let (min, max) = (parse_int(args[0])?, parse_int(args[1])?);
+                if min > max {
+                    return Err(verror!("rand({}, {}) has an empty range.", min, max));
+                }
+                Ok(Self::Rand(min, max))
+            }
+            Some((name, args)) => Err(verror!(
+                "No builtin function '{}' taking {} arguments.",
+                name,
+                args.len()
+            )),
+        }
+    }
+}
@@ -1,10 +1,17 @@
 use crate::error::ValidationError;
+use crate::value::Value;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Operator {
     ADD,
     SUB,
     SET,
+    /// Adds an item to a list or set.
+    INSERT,
+    /// Removes an item from a list or set.
+    REMOVE,
+    /// Removes every item from a list or set.
+    CLEAR,
 }
 
 impl Operator {
@@ -13,7 +20,36 @@ impl Operator {
             "+=" => Ok(Self::ADD),
             "-=" => Ok(Self::SUB),
             "=" => Ok(Self::SET),
+            "add" => Ok(Self::INSERT),
+            "remove" => Ok(Self::REMOVE),
+            "clear" => Ok(Self::CLEAR),
             _ => Err(verror!("No valid Operator matches {}", op)),
         }
     }
+
+    /// Applies the operator to a state variable's value.
+    pub fn apply(self, state_value: &mut Value, value: &Value) {
+        match self {
+            Self::SET => *state_value = value.widened_like(state_value),
+            Self::ADD => *state_value += value,
+            Self::SUB => *state_value -= value,
+            Self::INSERT => {
+                if let Value::List(items) = state_value {
+                    items.push(value.clone());
+                }
+            }
+            Self::REMOVE => {
+                if let Value::List(items) = state_value {
+                    if let Some(i) = items.iter().position(|item| item.equals(value)) {
+                        items.remove(i);
+                    }
+                }
+            }
+            Self::CLEAR => {
+                if let Value::List(items) = state_value {
+                    items.clear();
+                }
+            }
+        }
+    }
 }
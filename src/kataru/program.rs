@@ -0,0 +1,742 @@
+use crate::comparator::Comparator;
+use crate::conditional::{branch_len, compare, Conditional};
+use crate::declaration::Declaration;
+use crate::error::ValidationError;
+use crate::interpolate::{pieces, Piece};
+use crate::locale::{line_key, split_id, Locale};
+use crate::operand::{is_call, Operand};
+use crate::operator::Operator;
+use crate::rng::Rng;
+use crate::state::StateMod;
+use crate::structs::{
+    choice_key, split_speaker, Choice, Command, Config, DialogueText, Map, PassageLine,
+    RandomBranch, Speech, State, Story,
+};
+use crate::value::Value;
+
+/// An operand compiled to read from the slots of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Var(usize),
+    /// Visits of a passage, which are always zero for passages not in the story.
+    Visits(Option<usize>),
+    Chosen(usize),
+    Rand(i64, i64),
+    Len(usize),
+}
+
+/// Values of a running story, indexed by the slots of its program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Memory {
+    /// Values of state variables, or none for variables missing from the state.
+    pub state: Vec<Option<Value>>,
+    /// Number of times each passage has been entered.
+    pub visits: Vec<usize>,
+    /// Number of times each choice has been made.
+    pub chosen: Vec<usize>,
+}
+
+impl Expr {
+    /// Evaluates the expression, advancing the random number generator if needed.
+    pub fn eval(
+        &self,
+        program: &Program,
+        memory: &Memory,
+        rng: &mut Rng,
+    ) -> Result<Value, ValidationError> {
+        let missing = |slot: usize| verror!("No such state '{}'.", program.vars[slot]);
+        match *self {
+            Self::Var(slot) => memory.state[slot].clone().ok_or_else(|| missing(slot)),
+            Self::Visits(passage) => Ok(Value::Int(
+                passage.map_or(0, |passage| memory.visits[passage]) as i64,
+            )),
+            Self::Chosen(slot) => Ok(Value::Int(memory.chosen[slot] as i64)),
+            Self::Rand(min, max) => {
+                let value = Rng::to_range(rng.peek_u64(), min, max);
+                rng.next_u64();
+                Ok(Value::Int(value))
+            }
+            Self::Len(slot) => match &memory.state[slot] {
+                Some(Value::List(items)) => Ok(Value::Int(items.len() as i64)),
+                Some(value) => Err(verror!(
+                    "Only lists and sets have a length, not {:?}.",
+                    value
+                )),
+                None => Err(missing(slot)),
+            },
+        }
+    }
+}
+
+/// A conditional with its operand compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub lhs: Expr,
+    pub cmp: Comparator,
+    pub val: Value,
+}
+
+/// The value a state variable is modified by: a constant or a builtin function call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Value(Value),
+    Expr(Expr),
+}
+
+/// A compiled state modification, such as `gold +=: 1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mod {
+    pub slot: usize,
+    pub op: Operator,
+    pub value: Arg,
+}
+
+impl Mod {
+    /// Modifies the variable, enforcing its declaration if it has one.
+    pub fn apply(
+        &self,
+        program: &Program,
+        memory: &mut Memory,
+        rng: &mut Rng,
+    ) -> Result<(), ValidationError> {
+        let evaluated;
+        let value = match &self.value {
+            Arg::Value(value) => value,
+            Arg::Expr(expr) => {
+                evaluated = expr.eval(program, memory, rng)?;
+                &evaluated
+            }
+        };
+        let state_value = match &mut memory.state[self.slot] {
+            Some(state_value) => state_value,
+            None => return Err(verror!("No such state '{}'.", program.vars[self.slot])),
+        };
+        match &program.declarations[self.slot] {
+            Some(declaration) => declaration.apply(self.op, state_value, value),
+            None => {
+                self.op.apply(state_value, value);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Text shown to the player without its ID, translated if playing in another language.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label<'s> {
+    pub text: &'s str,
+    pub id: Option<&'s str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part<'s> {
+    Literal(&'s str),
+    Expr(Expr),
+}
+
+/// Narration or speech, split into literals and the expressions filled in when shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template<'s> {
+    pub label: Label<'s>,
+    pub parts: Vec<Part<'s>>,
+}
+
+impl<'s> Template<'s> {
+    pub fn render(
+        &self,
+        program: &Program,
+        memory: &Memory,
+        rng: &mut Rng,
+    ) -> Result<String, ValidationError> {
+        let mut result = String::with_capacity(self.label.text.len());
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => result.push_str(literal),
+                Part::Expr(expr) => result.push_str(&expr.eval(program, memory, rng)?.to_string()),
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// A character's line, with everything but its text worked out ahead of time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Speaker<'s> {
+    pub name: &'s str,
+    /// The speech to show, missing only its text.
+    pub speech: Speech,
+    pub template: Template<'s>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChoiceOp<'s> {
+    pub label: Label<'s>,
+    /// ID of the choice, or its text if it has none.
+    pub key: &'s str,
+    /// Slot of the number of times the choice has been made.
+    pub slot: usize,
+    pub once: bool,
+    pub set: Vec<Mod>,
+    pub goto: usize,
+    pub echo: Option<Template<'s>>,
+}
+
+/// What running a line does.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op<'s> {
+    Text(Template<'s>),
+    Dialogue(Vec<Speaker<'s>>),
+    /// A command, with the index of its handler if one is registered.
+    Command {
+        command: Command,
+        handler: Option<usize>,
+    },
+    Set(Vec<Mod>),
+    Choices(Vec<ChoiceOp<'s>>),
+    /// The conditionals of each branch, or none for `else`, along with their source.
+    Branches(Vec<(&'s str, Option<Condition>)>),
+    Random(Vec<f64>),
+    Goto(usize),
+    Call(usize),
+    Return,
+    /// A line that could not be compiled, which fails when run.
+    Invalid(String),
+}
+
+/// A flattened line of a passage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction<'s> {
+    pub line: &'s PassageLine,
+    pub op: Op<'s>,
+    /// Index of the line that follows this one.
+    pub next: usize,
+    /// Index of the first line of each nested block, or none if the block is empty.
+    pub blocks: Vec<Option<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledPassage<'s> {
+    pub name: &'s str,
+    pub instructions: Vec<Instruction<'s>>,
+}
+
+/// A story compiled to run without parsing: expressions are parsed ahead of time,
+/// variables and choices are kept in numbered slots, and passages are referred to
+/// by their index.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program<'s> {
+    /// Passages in the order of the story, and so sorted by name.
+    pub passages: Vec<CompiledPassage<'s>>,
+    /// Names of the state variables by slot.
+    pub vars: Vec<String>,
+    /// Keys of the choices in `Config::chosen` by slot.
+    pub choices: Vec<String>,
+    declarations: Vec<Option<Declaration>>,
+    var_slots: Map<String, usize>,
+    choice_slots: Map<String, usize>,
+}
+
+/// Finds the slots of the names in expressions.
+trait Slots {
+    fn var(&mut self, name: &str) -> Result<usize, ValidationError>;
+    fn choice(&mut self, key: &str) -> Result<usize, ValidationError>;
+    fn passage(&self, name: &str) -> Option<usize>;
+}
+
+fn compile_expr(slots: &mut impl Slots, operand: &Operand) -> Result<Expr, ValidationError> {
+    Ok(match *operand {
+        Operand::Var(var) => Expr::Var(slots.var(var)?),
+        Operand::Visits(passage) => Expr::Visits(slots.passage(passage)),
+        Operand::Chosen(passage, choice) => {
+            Expr::Chosen(slots.choice(&choice_key(passage, choice))?)
+        }
+        Operand::Rand(min, max) => Expr::Rand(min, max),
+        Operand::Len(var) => Expr::Len(slots.var(var)?),
+    })
+}
+
+fn compile_mods(slots: &mut impl Slots, set: &State) -> Result<Vec<Mod>, ValidationError> {
+    let mut mods = vec![];
+    for (key, value) in set {
+        let smod = StateMod::parse(key)?;
+        let value = match value {
+            Value::String(text) if is_call(text) => {
+                Arg::Expr(compile_expr(slots, &Operand::parse(text)?)?)
+            }
+            value => Arg::Value(value.clone()),
+        };
+        mods.push(Mod {
+            slot: slots.var(smod.var)?,
+            op: smod.op,
+            value,
+        });
+    }
+    Ok(mods)
+}
+
+/// Looks up slots of a compiled program, for state modified from outside the story.
+impl Slots for &Program<'_> {
+    fn var(&mut self, name: &str) -> Result<usize, ValidationError> {
+        let slot = self.var_slots.get(name).copied();
+        slot.ok_or_else(|| verror!("No such state '{}'.", name))
+    }
+
+    fn choice(&mut self, key: &str) -> Result<usize, ValidationError> {
+        let slot = self.choice_slots.get(key).copied();
+        slot.ok_or_else(|| verror!("No choice '{}'.", key))
+    }
+
+    fn passage(&self, name: &str) -> Option<usize> {
+        Program::passage(self, name)
+    }
+}
+
+/// Compiles lines, giving slots to the names it finds.
+struct Compiler<'c, 's> {
+    config: &'c Config,
+    locale: Option<&'s Locale>,
+    handlers: &'c [&'c str],
+    names: Vec<&'s str>,
+    vars: Map<String, usize>,
+    choices: Map<String, usize>,
+}
+
+impl Slots for Compiler<'_, '_> {
+    fn var(&mut self, name: &str) -> Result<usize, ValidationError> {
+        let slot = self.vars.len();
+        Ok(*self.vars.entry(name.to_string()).or_insert(slot))
+    }
+
+    fn choice(&mut self, key: &str) -> Result<usize, ValidationError> {
+        let slot = self.choices.len();
+        Ok(*self.choices.entry(key.to_string()).or_insert(slot))
+    }
+
+    fn passage(&self, name: &str) -> Option<usize> {
+        self.names.binary_search(&name).ok()
+    }
+}
+
+impl<'c, 's> Compiler<'c, 's> {
+    fn target(&self, name: &str) -> Result<usize, ValidationError> {
+        self.passage(name)
+            .ok_or_else(|| verror!("No passage '{}'.", name))
+    }
+
+    fn label(&self, text: &'s str, id: Option<&'s str>) -> Label<'s> {
+        let (source, own_id) = split_id(text);
+        let id = id.or(own_id);
+        let text = match (self.locale, id) {
+            (Some(locale), Some(id)) => locale.get(id).unwrap_or(source),
+            _ => source,
+        };
+        Label { text, id }
+    }
+
+    fn template(
+        &mut self,
+        text: &'s str,
+        id: Option<&'s str>,
+    ) -> Result<Template<'s>, ValidationError> {
+        let label = self.label(text, id);
+        let mut parts = vec![];
+        for piece in pieces(label.text)? {
+            parts.push(match piece {
+                Piece::Literal(literal) => Part::Literal(literal),
+                Piece::Expression(expression) => {
+                    Part::Expr(compile_expr(self, &Operand::parse(expression)?)?)
+                }
+            });
+        }
+        Ok(Template { label, parts })
+    }
+
+    fn condition(&mut self, expression: &str) -> Result<Condition, ValidationError> {
+        let conditional = Conditional::parse(expression)?;
+        Ok(Condition {
+            lhs: compile_expr(self, &conditional.lhs)?,
+            cmp: conditional.cmp,
+            val: conditional.val,
+        })
+    }
+
+    fn command(&self, command: Command) -> Op<'s> {
+        let handler = self.handlers.iter().position(|name| *name == command.name);
+        Op::Command { command, handler }
+    }
+
+    fn speaker(
+        &mut self,
+        key: &'s str,
+        text: &'s DialogueText,
+    ) -> Result<Speaker<'s>, ValidationError> {
+        let (name, emotion) = split_speaker(key);
+        let mut speech = match text {
+            DialogueText::Text(_) => Speech::default(),
+            DialogueText::Speech(speech) => Speech {
+                text: String::new(),
+                ..speech.clone()
+            },
+        };
+        speech.id = text.id().map(str::to_string);
+        if speech.emotion.is_none() {
+            speech.emotion = emotion.map(str::to_string);
+        }
+        if speech.voice.is_none() {
+            let character = self.config.characters.get(name);
+            speech.voice = speech
+                .id
+                .as_deref()
+                .and_then(|id| character?.voice_clip(id));
+        }
+        Ok(Speaker {
+            name,
+            speech,
+            template: self.template(text.text(), text.id())?,
+        })
+    }
+
+    fn choice_op(
+        &mut self,
+        passage: &str,
+        text: &'s str,
+        choice: &'s Choice,
+    ) -> Result<ChoiceOp<'s>, ValidationError> {
+        let key = line_key(text);
+        Ok(ChoiceOp {
+            label: self.label(text, None),
+            key,
+            slot: self.choice(&choice_key(passage, key))?,
+            once: choice.once(),
+            set: match choice.set() {
+                Some(set) => compile_mods(self, set)?,
+                None => vec![],
+            },
+            goto: self.target(choice.passage())?,
+            echo: match choice.text() {
+                Some(text) => Some(self.template(text, None)?),
+                None => None,
+            },
+        })
+    }
+
+    fn op(&mut self, passage: &str, line: &'s PassageLine) -> Result<Op<'s>, ValidationError> {
+        // Dialogue spoken by a declared command is that command with a string argument.
+        if let PassageLine::Dialogue(dialogue) = line {
+            let is_command = |name: &str| {
+                self.config.commands.contains_key(name) || self.handlers.contains(&name)
+            };
+            if let Some(command) = Command::from_dialogue(dialogue, is_command) {
+                return Ok(self.command(command));
+            }
+        }
+        Ok(match line {
+            PassageLine::Command(command) => self.command(command.clone()),
+            PassageLine::SetCmd(set) => Op::Set(compile_mods(self, &set.set)?),
+            PassageLine::Choices(choices) => {
+                let mut ops = vec![];
+                for (text, choice) in &choices.choices {
+                    ops.push(self.choice_op(passage, text, choice)?);
+                }
+                Op::Choices(ops)
+            }
+            PassageLine::Branches(branches) => {
+                let mut conditions = vec![];
+                for expression in branches.keys() {
+                    let condition = match expression.as_str() {
+                        "else" => None,
+                        _ => Some(self.condition(expression)?),
+                    };
+                    conditions.push((expression.as_str(), condition));
+                }
+                Op::Branches(conditions)
+            }
+            PassageLine::Random(random) => {
+                Op::Random(random.random.iter().map(RandomBranch::weight).collect())
+            }
+            PassageLine::Goto(goto) => Op::Goto(self.target(&goto.goto)?),
+            PassageLine::Call(call) => Op::Call(self.target(&call.call)?),
+            PassageLine::Return(_) => Op::Return,
+            PassageLine::Text(text) => Op::Text(self.template(text, None)?),
+            PassageLine::Dialogue(dialogue) => {
+                let mut speakers = vec![];
+                for (key, text) in dialogue {
+                    speakers.push(self.speaker(key, text)?);
+                }
+                Op::Dialogue(speakers)
+            }
+        })
+    }
+
+    /// Flattens lines and their nested blocks, recording where each line continues.
+    /// The last line of a block continues at `end`, skipping any sibling blocks.
+    fn flatten(
+        &mut self,
+        passage: &str,
+        lines: &'s [PassageLine],
+        end: usize,
+        instructions: &mut Vec<Instruction<'s>>,
+    ) {
+        for (i, line) in lines.iter().enumerate() {
+            let index = instructions.len();
+            let next = if i + 1 == lines.len() {
+                end
+            } else {
+                index + branch_len(std::slice::from_ref(line))
+            };
+            let mut start = index + 1;
+            let blocks = line
+                .blocks()
+                .into_iter()
+                .map(|block| {
+                    let first = start;
+                    start += branch_len(block);
+                    Some(first).filter(|_| !block.is_empty())
+                })
+                .collect();
+            let op = self
+                .op(passage, line)
+                .unwrap_or_else(|e| Op::Invalid(e.message));
+            instructions.push(Instruction {
+                line,
+                op,
+                next,
+                blocks,
+            });
+            for block in line.blocks() {
+                self.flatten(passage, block, next, instructions);
+            }
+        }
+    }
+}
+
+/// Lists the names of slots in order.
+fn slot_names(slots: &Map<String, usize>) -> Vec<String> {
+    let mut names = vec![String::new(); slots.len()];
+    for (name, slot) in slots {
+        names[*slot] = name.clone();
+    }
+    names
+}
+
+impl<'s> Program<'s> {
+    /// Compiles a story to show in the language of `locale`, running the commands
+    /// named in `handlers` through the handler at the same index.
+    /// Lines that cannot be compiled fail when they are run.
+    pub fn compile(
+        config: &Config,
+        story: &'s Story,
+        locale: Option<&'s Locale>,
+        handlers: &[&str],
+    ) -> Self {
+        let mut compiler = Compiler {
+            config,
+            locale,
+            handlers,
+            names: story.keys().map(String::as_str).collect(),
+            vars: Map::new(),
+            choices: Map::new(),
+        };
+        for var in config.state.keys() {
+            compiler.var(var).unwrap();
+        }
+        let passages = story
+            .iter()
+            .map(|(name, lines)| {
+                let mut instructions = vec![];
+                compiler.flatten(name, lines, branch_len(lines), &mut instructions);
+                CompiledPassage { name, instructions }
+            })
+            .collect();
+        let vars = slot_names(&compiler.vars);
+        Self {
+            passages,
+            declarations: vars
+                .iter()
+                .map(|var| config.declarations.get(var).cloned())
+                .collect(),
+            vars,
+            choices: slot_names(&compiler.choices),
+            var_slots: compiler.vars,
+            choice_slots: compiler.choices,
+        }
+    }
+
+    /// Returns the index of the passage with the given name.
+    pub fn passage(&self, name: &str) -> Option<usize> {
+        self.passages
+            .binary_search_by(|passage| passage.name.cmp(name))
+            .ok()
+    }
+
+    /// Compiles state modifications made from outside the story.
+    pub fn mods(&self, set: &State) -> Result<Vec<Mod>, ValidationError> {
+        compile_mods(&mut &*self, set)
+    }
+
+    /// Reads the values of the program's slots from a config.
+    pub fn load(&self, config: &Config) -> Memory {
+        Memory {
+            state: self
+                .vars
+                .iter()
+                .map(|var| config.state.get(var).cloned())
+                .collect(),
+            visits: self
+                .passages
+                .iter()
+                .map(|passage| config.visits.get(passage.name).copied().unwrap_or(0))
+                .collect(),
+            chosen: self
+                .choices
+                .iter()
+                .map(|key| config.chosen.get(key).copied().unwrap_or(0))
+                .collect(),
+        }
+    }
+
+    /// Writes the values of the program's slots back to a config.
+    /// Passages and choices that were never reached are left out.
+    pub fn save(&self, memory: &Memory, config: &mut Config) {
+        for (var, value) in self.vars.iter().zip(&memory.state) {
+            if let Some(value) = value {
+                config.state.insert(var.clone(), value.clone());
+            }
+        }
+        for (passage, &count) in self.passages.iter().zip(&memory.visits) {
+            if count > 0 {
+                config.visits.insert(passage.name.to_string(), count);
+            }
+        }
+        for (key, &count) in self.choices.iter().zip(&memory.chosen) {
+            if count > 0 {
+                config.chosen.insert(key.clone(), count);
+            }
+        }
+    }
+}
+
+/// Evaluates the conditionals of branches in order and returns the index of the first
+/// that holds, falling back to the `else` branch if there is one.
+pub fn take_branch(
+    branches: &[(&str, Option<Condition>)],
+    program: &Program,
+    memory: &Memory,
+    rng: &mut Rng,
+) -> Result<Option<usize>, ValidationError> {
+    let mut otherwise = None;
+    for (i, (_, condition)) in branches.iter().enumerate() {
+        match condition {
+            None => otherwise = Some(i),
+            Some(condition) => {
+                let value = condition.lhs.eval(program, memory, rng)?;
+                if compare(condition.cmp, &value, &condition.val)? {
+                    return Ok(Some(i));
+                }
+            }
+        }
+    }
+    Ok(otherwise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::{config_with, story};
+
+    #[test]
+    fn test_compile() {
+        let config = config_with("{state: {gold: 3}, visits: {End: 2}}");
+        let story = story(
+            r#"
+Start:
+  - if gold > 1:
+      - You have {gold} gold.
+    else: []
+  - set: { gold -=: 'rand(1, 2)' }
+  - choices:
+      Leave @line:leave: End
+  - goto: Nowhere
+End:
+  - if visits(End) >= 1: [Back again.]
+"#,
+        );
+        let program = Program::compile(&config, &story, None, &[]);
+        assert_eq!(program.passage("End"), Some(0));
+        assert_eq!(program.passage("Start"), Some(1));
+        assert_eq!(program.vars, vec!["gold"]);
+        assert_eq!(program.choices, vec!["Start/leave"]);
+
+        let ops: Vec<&Op> = program.passages[1]
+            .instructions
+            .iter()
+            .map(|instruction| &instruction.op)
+            .collect();
+        let condition = Condition {
+            lhs: Expr::Var(0),
+            cmp: Comparator::GT,
+            val: Value::Int(1),
+        };
+        assert_eq!(
+            ops[0],
+            &Op::Branches(vec![("if gold > 1", Some(condition)), ("else", None)])
+        );
+        assert_eq!(
+            program.passages[1].instructions[0].blocks,
+            vec![Some(1), None]
+        );
+        let label = Label {
+            text: "You have {gold} gold.",
+            id: None,
+        };
+        let parts = vec![
+            Part::Literal("You have "),
+            Part::Expr(Expr::Var(0)),
+            Part::Literal(" gold."),
+        ];
+        assert_eq!(ops[1], &Op::Text(Template { label, parts }));
+        let set = Mod {
+            slot: 0,
+            op: Operator::SUB,
+            value: Arg::Expr(Expr::Rand(1, 2)),
+        };
+        assert_eq!(ops[2], &Op::Set(vec![set]));
+        match ops[3] {
+            Op::Choices(choices) => {
+                assert_eq!((choices[0].label.text, choices[0].key), ("Leave", "leave"));
+                assert_eq!((choices[0].slot, choices[0].goto), (0, 0));
+            }
+            op => panic!("Expected choices, not {:?}", op),
+        }
+        assert_eq!(ops[4], &Op::Invalid("No passage 'Nowhere'.".to_string()));
+
+        let memory = program.load(&config);
+        assert_eq!(memory.state, vec![Some(Value::Int(3))]);
+        assert_eq!((&memory.visits, &memory.chosen), (&vec![2, 0], &vec![0]));
+        let mut rng = Rng::new(0);
+        let counter = |expr: Expr, rng: &mut Rng| expr.eval(&program, &memory, rng).unwrap();
+        assert_eq!(counter(Expr::Visits(Some(0)), &mut rng), Value::Int(2));
+        assert_eq!(counter(Expr::Visits(None), &mut rng), Value::Int(0));
+        assert_eq!(counter(Expr::Chosen(0), &mut rng), Value::Int(0));
+
+        let mut saved = config.clone();
+        let mut memory = memory;
+        memory.state[0] = Some(Value::Int(1));
+        memory.chosen[0] = 1;
+        program.save(&memory, &mut saved);
+        assert_eq!(saved.state["gold"], Value::Int(1));
+        assert_eq!(saved.visits.len(), 1);
+        assert_eq!(saved.chosen["Start/leave"], 1);
+        let branch = match &program.passages[0].instructions[0].op {
+            Op::Branches(branches) => {
+                take_branch(branches, &program, &program.load(&config), &mut rng)
+            }
+            op => panic!("Expected branches, not {:?}", op),
+        };
+        assert_eq!(branch.unwrap(), Some(0));
+
+        let mut set = State::new();
+        set.insert("silver +=".to_string(), Value::Int(1));
+        assert!(program.mods(&set).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::error::ValidationError;
+use crate::structs::{Config, Passage, PassageLine, Story};
+use crate::validate::validate;
+
+/// Flattens lines and their nested blocks in the order the runner indexes them.
+fn flatten<'a>(lines: &'a [PassageLine], flat: &mut Vec<&'a PassageLine>) {
+    for line in lines {
+        flat.push(line);
+        for block in line.blocks() {
+            flatten(block, flat);
+        }
+    }
+}
+
+/// Finds the index of the new passage closest to the given line of the old passage.
+/// Prefers an identical line, then the line after an identical predecessor,
+/// then the same index.
+pub fn remap_line(old: &Passage, new: &Passage, line: usize) -> usize {
+    let (mut old_lines, mut new_lines) = (vec![], vec![]);
+    flatten(old, &mut old_lines);
+    flatten(new, &mut new_lines);
+    if line >= old_lines.len() {
+        return new_lines.len();
+    }
+    let nearest = |target: &PassageLine| {
+        (0..new_lines.len())
+            .filter(|&j| new_lines[j] == target)
+            .min_by_key(|&j| (j as isize - line as isize).abs())
+    };
+    if let Some(j) = nearest(old_lines[line]) {
+        return j;
+    }
+    if line > 0 {
+        if let Some(j) = nearest(old_lines[line - 1]) {
+            return j + 1;
+        }
+    }
+    line.min(new_lines.len())
+}
+
+/// Checks that a changed story can replace the running one, and moves the current
+/// line and the call stack onto it. Leaves the config untouched on failure.
+pub fn reload(config: &mut Config, old: &Story, new: &Story) -> Result<(), ValidationError> {
+    validate(config, new)?;
+    let passages = std::iter::once(&config.passage).chain(config.stack.iter().map(|f| &f.passage));
+    for passage in passages {
+        if !new.contains_key(passage) {
+            return Err(verror!("Passage '{}' is in use but was removed.", passage));
+        }
+    }
+    config.line = remap_line(&old[&config.passage], &new[&config.passage], config.line);
+    for frame in &mut config.stack {
+        frame.line = remap_line(&old[&frame.passage], &new[&frame.passage], frame.line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::{config_with, story};
+
+    #[test]
+    fn test_reload() {
+        let old = story("{Start: [One., Two., Three.], Shop: [Hi.]}");
+        let mut config = config_with("{line: 1}");
+
+        // Inserting lines above the current one keeps it on the same text.
+        let new = story("{Start: [Zero., One., Two., Three.], Shop: [Hi.]}");
+        reload(&mut config, &old, &new).unwrap();
+        assert_eq!(config.line, 2);
+
+        // Editing the current line moves to the line after its old predecessor.
+        let newer = story("{Start: [Zero., One., Deux., Three.], Shop: [Hi.]}");
+        reload(&mut config, &new, &newer).unwrap();
+        assert_eq!(config.line, 2);
+
+        // Removing the current passage or breaking validation keeps the old version.
+        assert!(reload(&mut config, &newer, &story("{Shop: [Hi.]}")).is_err());
+        assert!(reload(&mut config, &newer, &story("{Start: [{goto: Nowhere}]}")).is_err());
+        assert_eq!(config.line, 2);
+    }
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+/// Seedable counter-based random number generator.
+/// Its entire state is the seed and the number of values drawn so far,
+/// so saving a config allows random playthroughs to be replayed exactly.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rng {
+    pub seed: u64,
+    #[serde(default)]
+    pub position: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, position: 0 }
+    }
+
+    /// Returns the value at the given position using SplitMix64.
+    fn value_at(&self, position: u64) -> u64 {
+        let mut z = self
+            .seed
+            .wrapping_add(position.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns the next random value without advancing.
+    pub fn peek_u64(&self) -> u64 {
+        self.value_at(self.position + 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.position += 1;
+        self.value_at(self.position)
+    }
+
+    /// Returns a random float in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Maps a random value onto the inclusive range [min, max].
+    pub fn to_range(value: u64, min: i64, max: i64) -> i64 {
+        let span = (max.wrapping_sub(min) as u64).wrapping_add(1);
+        if span == 0 {
+            value as i64
+        } else {
+            min.wrapping_add((value % span) as i64)
+        }
+    }
+
+    /// Returns a random integer in the inclusive range [min, max].
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        Self::to_range(self.next_u64(), min, max)
+    }
+
+    /// Picks an index with probability proportional to its weight.
+    /// Returns None if there are no positive weights.
+    pub fn choose_weighted(&mut self, weights: &[f64]) -> Option<usize> {
+        let total: f64 = weights.iter().filter(|w| **w > 0.).sum();
+        if total <= 0. {
+            return None;
+        }
+        let mut target = self.next_f64() * total;
+        for (i, weight) in weights.iter().enumerate() {
+            if *weight <= 0. {
+                continue;
+            }
+            if target < *weight {
+                return Some(i);
+            }
+            target -= weight;
+        }
+        weights.iter().rposition(|w| *w > 0.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_replay() {
+        let mut rng = Rng::new(42);
+        let first: Vec<i64> = (0..10).map(|_| rng.range(1, 6)).collect();
+        assert!(first.iter().all(|n| (1..=6).contains(n)));
+
+        // Restoring a saved position continues the same sequence.
+        let mut saved = Rng::new(42);
+        saved.position = 5;
+        let rest: Vec<i64> = (0..5).map(|_| saved.range(1, 6)).collect();
+        assert_eq!(rest, first[5..]);
+
+        assert_eq!(rng.choose_weighted(&[0., 1., 0.]), Some(1));
+        assert_eq!(rng.choose_weighted(&[0., 0.]), None);
+
+        // The full range of integers does not overflow.
+        Rng::to_range(u64::MAX, i64::MIN, i64::MAX);
+        assert_eq!(Rng::to_range(7, i64::MIN, i64::MAX), 7);
+    }
+}
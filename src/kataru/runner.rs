@@ -1,116 +1,1200 @@
-pub use crate::conditional::{branch_len, take_branch};
+pub use crate::conditional::branch_len;
 pub use crate::error::ValidationError;
-pub use crate::state::update_state;
-pub use crate::structs::{Branches, Config, Passage, PassageLine, Story};
+pub use crate::event::{Event, Line};
+pub use crate::history::Record;
+use crate::locale::{migrate_chosen, Locale};
+pub use crate::observer::Observer;
+use crate::program::{self, ChoiceOp, Label, Memory, Mod, Op, Program, Template};
+pub use crate::structs::{
+    choice_key, split_speaker, Branches, CharacterData, Characters, Choice, Choices, Command,
+    Commands, Config, DialogueText, Passage, PassageLine, Speech, StackFrame, State, Story,
+};
 pub use crate::validate::validate;
+use crate::value::Value;
 pub use colored::*;
+use log::{debug, trace};
+use std::collections::VecDeque;
+use std::rc::Rc;
 
+pub use crate::declaration::ValueType;
+
+/// Runs a command on behalf of the host.
+pub type CommandHandler<'r> = Box<dyn FnMut(&Command) + 'r>;
+
+/// Runs a story compiled to a `Program`, reading state from the program's slots.
+/// The config is only brought up to date when the game is saved with `save`.
 pub struct Runner<'r> {
-    pub config: &'r mut Config,
+    config: &'r mut Config,
     pub story: &'r Story,
-    pub line: usize,
-    pub passage: &'r Passage,
-    pub lines: Vec<&'r PassageLine>,
+    program: Rc<Program<'r>>,
+    memory: Memory,
+    /// Index of the current passage in the program.
+    passage: usize,
+    /// Index of the line that runs next in the current passage.
+    line: usize,
+    /// Passages and lines to return to, as indices in the program.
+    stack: Vec<(usize, usize)>,
+    /// Lines shown and choices made, copied to the config when saving.
+    history: VecDeque<Record>,
+    /// Handlers of registered commands, by command name.
+    /// Commands without one are returned as events.
+    handlers: Vec<(String, CommandHandler<'r>)>,
+    /// Signatures of the registered commands, which are not saved with the config.
+    commands: Commands,
+    /// Events produced but not yet returned from `advance`.
+    events: VecDeque<Event>,
+    /// Choices of the current line waiting to be made with `choose`.
+    choices: Option<Vec<usize>>,
+    observers: Vec<Box<dyn Observer + 'r>>,
+    /// Translations of the lines shown, if playing in another language.
+    locale: Option<&'r Locale>,
+    /// Whether observers have been told about the starting passage.
+    started: bool,
+    ended: bool,
 }
 
 impl<'r> Runner<'r> {
-    pub fn new(config: &'r mut Config, story: &'r Story) -> Self {
-        // Flatten dialogue lines
-        let passage = &story[&config.passage];
+    /// Starts or resumes playing a story from the position saved in the config.
+    /// Fails if the config names passages the story does not have.
+    pub fn new(config: &'r mut Config, story: &'r Story) -> Result<Self, ValidationError> {
+        config.init_state();
+        migrate_chosen(config, story);
         let mut runner = Self {
             config,
             story,
+            program: Rc::default(),
+            memory: Memory::default(),
+            passage: 0,
             line: 0,
-            lines: vec![],
-            passage,
+            stack: vec![],
+            history: VecDeque::new(),
+            handlers: vec![],
+            commands: Commands::new(),
+            events: VecDeque::new(),
+            choices: None,
+            observers: vec![],
+            locale: None,
+            started: false,
+            ended: false,
         };
-        runner.load_lines(passage);
+        runner.compile();
+        runner.restore()?;
+        // A fresh config has not visited anything yet, so count the starting passage.
+        if runner.config.visits.is_empty() {
+            runner.visit();
+        }
         runner
+            .events
+            .push_back(Event::PassageEntered(runner.config.passage.clone()));
+        Ok(runner)
     }
 
-    fn load_lines(&mut self, lines: &'r [PassageLine]) {
-        for line in lines {
-            match line {
-                PassageLine::Branches(branches) => {
-                    self.lines.push(&line);
-                    for (_expression, branch_lines) in branches {
-                        self.load_lines(branch_lines)
-                    }
-                }
-                _ => self.lines.push(&line),
+    /// Compiles the story for the current locale and command handlers.
+    fn compile(&mut self) {
+        let handlers: Vec<&str> = self
+            .handlers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let program = Program::compile(self.config, self.story, self.locale, &handlers);
+        self.program = Rc::new(program);
+    }
+
+    /// Reads the position, the history and the slots of the program from the config.
+    fn restore(&mut self) -> Result<(), ValidationError> {
+        let program = &self.program;
+        let index = |name: &str| {
+            program
+                .passage(name)
+                .ok_or_else(|| verror!("No passage '{}'.", name))
+        };
+        self.passage = index(&self.config.passage)?;
+        self.line = self.config.line;
+        self.stack = self
+            .config
+            .stack
+            .iter()
+            .map(|frame| Ok((index(&frame.passage)?, frame.line)))
+            .collect::<Result<_, ValidationError>>()?;
+        self.history = self.config.history.clone();
+        self.memory = program.load(self.config);
+        Ok(())
+    }
+
+    /// Compiles the story again, as for a new locale or command handler,
+    /// carrying the state of play over to the new program. Passages and lines
+    /// keep their indices, since they come from the same story.
+    fn recompile(&mut self) {
+        self.program.save(&self.memory, self.config);
+        self.compile();
+        self.memory = self.program.load(self.config);
+    }
+
+    /// Writes the state of play back to the config and returns it, as for saving the game.
+    pub fn save(&mut self) -> &Config {
+        self.program.save(&self.memory, self.config);
+        self.config.history = self.history.clone();
+        let program = &self.program;
+        self.config.passage = program.passages[self.passage].name.to_string();
+        self.config.line = self.line;
+        self.config.stack = self
+            .stack
+            .iter()
+            .map(|&(passage, line)| StackFrame {
+                passage: program.passages[passage].name.to_string(),
+                line,
+            })
+            .collect();
+        self.config
+    }
+
+    /// Returns the value of a state variable.
+    pub fn get(&self, var: &str) -> Option<&Value> {
+        let slot = self.program.vars.iter().position(|other| other == var)?;
+        self.memory.state[slot].as_ref()
+    }
+
+    /// Returns the state variables with their values, in order of their names.
+    pub fn state(&self) -> Vec<(&str, &Value)> {
+        let mut state: Vec<(&str, &Value)> = self
+            .program
+            .vars
+            .iter()
+            .zip(&self.memory.state)
+            .filter_map(|(var, value)| Some((var.as_str(), value.as_ref()?)))
+            .collect();
+        state.sort_by_key(|&(var, _)| var);
+        state
+    }
+
+    /// Declares a command and runs `handler` whenever the story reaches it.
+    pub fn register_command(
+        &mut self,
+        name: &str,
+        signature: Vec<ValueType>,
+        handler: impl FnMut(&Command) + 'r,
+    ) {
+        self.commands.insert(name.to_string(), signature);
+        match self.handlers.iter_mut().find(|(other, _)| other == name) {
+            Some((_, old)) => *old = Box::new(handler),
+            None => self.handlers.push((name.to_string(), Box::new(handler))),
+        }
+        self.recompile();
+    }
+
+    fn run_command(&mut self, command: &Command, handler: Option<usize>) {
+        self.next_line();
+        match handler {
+            Some(i) => (self.handlers[i].1)(command),
+            None => self.events.push_back(Event::Command(command.clone())),
+        }
+    }
+
+    /// Registers an observer to be called back as the story runs.
+    pub fn observe(&mut self, observer: impl Observer + 'r) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Shows lines in the language of a locale, falling back to the story's own text
+    /// for lines that are not translated.
+    pub fn localize(&mut self, locale: &'r Locale) {
+        self.locale = Some(locale);
+        self.recompile();
+    }
+
+    /// Prepares a choice to be shown, along with its ID.
+    fn line(label: &Label) -> Line {
+        Line {
+            text: label.text.to_string(),
+            id: label.id.map(str::to_string),
+        }
+    }
+
+    /// Shows narration, filling in its expressions, or reports why they could not be filled in.
+    fn narrate(&mut self, template: &Template) {
+        match self.render(template) {
+            Ok(text) => self.emit(Event::Text(Line {
+                text,
+                id: template.label.id.map(str::to_string),
+            })),
+            Err(e) => self.fail(e),
+        }
+    }
+
+    fn render(&mut self, template: &Template) -> Result<String, ValidationError> {
+        template.render(&self.program, &self.memory, &mut self.config.rng)
+    }
+
+    /// Reports a line that failed as it ran. The story carries on past it.
+    fn fail(&mut self, e: ValidationError) {
+        debug!("{}", e);
+        self.events.push_back(Event::Error(e.message));
+    }
+
+    /// Returns the lines shown and choices made so far, oldest first.
+    pub fn history(&self) -> &VecDeque<Record> {
+        &self.history
+    }
+
+    /// Records a line in the history, dropping the oldest records over the limit.
+    fn record(&mut self, record: Record) {
+        self.history.push_back(record);
+        while self.history.len() > self.config.history_limit {
+            self.history.pop_front();
+        }
+    }
+
+    /// Queues an event, recording lines shown to the player in the history.
+    fn emit(&mut self, event: Event) {
+        match &event {
+            Event::Text(line) => self.record(Record::Text(line.text.clone())),
+            Event::Dialogue { character, speech } => self.record(Record::Dialogue {
+                character: character.clone(),
+                speech: speech.clone(),
+            }),
+            _ => (),
+        }
+        self.events.push_back(event);
+    }
+
+    /// Validates the story against the config and the commands registered with the runner.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut config = self.config.clone();
+        config.commands.extend(self.commands.clone());
+        validate(&config, self.story)
+    }
+
+    /// Returns the metadata of a character, if declared in the config.
+    pub fn character(&self, name: &str) -> Option<&CharacterData> {
+        self.config.characters.get(name)
+    }
+
+    /// Returns the characters declared in the config.
+    pub fn characters(&self) -> &Characters {
+        &self.config.characters
+    }
+
+    fn visit(&mut self) {
+        self.memory.visits[self.passage] += 1;
+    }
+
+    /// Moves on to the line after the current one.
+    fn next_line(&mut self) {
+        self.line = self.program.passages[self.passage].instructions[self.line].next;
+    }
+
+    /// Enters the nested block at the given index of the current line,
+    /// or moves past the line if there is no block to enter.
+    fn enter_block(&mut self, block: Option<usize>) {
+        let instruction = &self.program.passages[self.passage].instructions[self.line];
+        match block.and_then(|i| instruction.blocks[i]) {
+            Some(start) => self.line = start,
+            None => self.next_line(),
+        }
+    }
+
+    fn load_passage(&mut self, passage: usize, line: usize) {
+        let exited = self.program.passages[self.passage].name;
+        let name = self.program.passages[passage].name;
+        debug!("Entering passage {} at line {}.", name, line);
+        for observer in &mut self.observers {
+            observer.passage_exited(exited);
+            observer.passage_entered(name);
+        }
+        self.passage = passage;
+        self.line = line;
+        self.events
+            .push_back(Event::PassageEntered(name.to_string()));
+    }
+
+    fn goto(&mut self, passage: usize) {
+        self.load_passage(passage, 0);
+        self.visit();
+    }
+
+    /// Enters a passage, remembering to come back to the line after the current one.
+    fn call(&mut self, passage: usize) {
+        let line = self.program.passages[self.passage].instructions[self.line].next;
+        self.stack.push((self.passage, line));
+        self.goto(passage);
+    }
+
+    /// Returns to the caller of the current passage.
+    /// Returns false if the current passage was not called.
+    fn ret(&mut self) -> bool {
+        match self.stack.pop() {
+            Some((passage, line)) => {
+                self.load_passage(passage, line);
+                true
             }
+            None => false,
+        }
+    }
+
+    /// Applies state modifications, reporting the variables whose values changed.
+    pub fn set(&mut self, set: &State) -> Result<(), ValidationError> {
+        let mods = self.program.mods(set)?;
+        self.apply(&mods)
+    }
+
+    /// Applies compiled state modifications, reporting the variables whose values
+    /// changed in order of their names.
+    fn apply(&mut self, mods: &[Mod]) -> Result<(), ValidationError> {
+        let program = Rc::clone(&self.program);
+        let mut old: Vec<(usize, Option<Value>)> = vec![];
+        for m in mods {
+            if old.iter().all(|(slot, _)| *slot != m.slot) {
+                old.push((m.slot, self.memory.state[m.slot].clone()));
+            }
+        }
+        let result = mods
+            .iter()
+            .try_for_each(|m| m.apply(&program, &mut self.memory, &mut self.config.rng));
+        old.sort_by(|(a, _), (b, _)| program.vars[*a].cmp(&program.vars[*b]));
+        for (slot, old_value) in old {
+            let value = match &self.memory.state[slot] {
+                Some(value) if old_value.as_ref() != Some(value) => value,
+                _ => continue,
+            };
+            let var = &program.vars[slot];
+            let old_value = old_value.as_ref().unwrap_or(&Value::None);
+            debug!("Set {}: {:?} -> {:?}", var, old_value, value);
+            for observer in &mut self.observers {
+                observer.state_changed(var, old_value, value);
+            }
+            self.events.push_back(Event::StateChanged {
+                var: var.clone(),
+                value: value.clone(),
+            });
+        }
+        result
+    }
+
+    /// Makes a choice, counting it by its ID so that translations do not change it.
+    fn make_choice(&mut self, choice: &ChoiceOp) {
+        self.memory.chosen[choice.slot] += 1;
+        let shown = choice.label.text.to_string();
+        self.record(Record::Choice(shown));
+        let passage = self.program.passages[self.passage].name;
+        debug!("Chose '{}' in {}.", choice.key, passage);
+        for observer in &mut self.observers {
+            observer.choice_made(passage, choice.key);
+        }
+        if let Err(e) = self.apply(&choice.set) {
+            self.fail(e);
+        }
+        self.goto(choice.goto);
+        if let Some(echo) = &choice.echo {
+            self.narrate(echo);
+        }
+    }
+
+    /// Returns the choices of the current line, if it has any.
+    fn choice_ops<'p>(program: &'p Program<'r>, passage: usize, line: usize) -> &'p [ChoiceOp<'r>] {
+        match &program.passages[passage].instructions[line].op {
+            Op::Choices(choices) => choices,
+            _ => &[],
         }
     }
-    fn goto(&mut self, passage_name: &str) {
-        self.config.passage = passage_name.to_string();
-        self.config.line = 0;
-        self.passage = &self.story[&self.config.passage];
-        self.lines = vec![];
-        self.load_lines(self.passage);
+
+    /// Makes the choice at `index` of the choices last presented.
+    pub fn choose(&mut self, index: usize) -> Result<(), ValidationError> {
+        let available = match self.choices.take() {
+            Some(available) => available,
+            None => return Err(verror!("There are no choices to make.")),
+        };
+        match available.get(index) {
+            Some(&i) => {
+                let program = Rc::clone(&self.program);
+                let choices = Self::choice_ops(&program, self.passage, self.line);
+                self.make_choice(&choices[i]);
+                Ok(())
+            }
+            None => {
+                let count = available.len();
+                self.choices = Some(available);
+                Err(verror!("No choice {} among {} choices.", index, count))
+            }
+        }
     }
 
-    fn handle_line(&mut self, input: &str, line: &'r PassageLine) -> &'r PassageLine {
-        match line {
-            // When a choice is encountered, it should first be returned for display.
-            // Second time its encountered,
-            PassageLine::SetCmd(set) => {
-                update_state(&mut self.config.state, &set.set).unwrap();
-                self.config.line += 1;
-                &PassageLine::Continue
+    /// Runs the current line, queueing any events it produces.
+    fn handle_line(&mut self) {
+        let program = Rc::clone(&self.program);
+        let instruction = &program.passages[self.passage].instructions[self.line];
+        match &instruction.op {
+            Op::Command { command, handler } => self.run_command(command, *handler),
+            Op::Set(mods) => {
+                self.next_line();
+                if let Err(e) = self.apply(mods) {
+                    self.fail(e);
+                }
             }
-            PassageLine::Choices(choices) => {
-                if choices.choices.contains_key(input) {
-                    self.goto(&choices.choices[input]);
-                    &PassageLine::Continue
-                } else if input.is_empty() {
-                    line
+            Op::Choices(choices) => {
+                // Hide once-only choices already taken.
+                let available: Vec<usize> = (0..choices.len())
+                    .filter(|&i| !choices[i].once || self.memory.chosen[choices[i].slot] == 0)
+                    .collect();
+                if available.is_empty() {
+                    // Every choice has been used up, so move past them.
+                    self.next_line();
                 } else {
-                    &PassageLine::InvalidChoice
+                    // Stay on this line until `choose` jumps away.
+                    self.choices = Some(available);
+                }
+            }
+            Op::Branches(branches) => {
+                match program::take_branch(branches, &program, &self.memory, &mut self.config.rng) {
+                    Ok(branch) => {
+                        match branch {
+                            Some(i) => trace!("Took branch '{}'.", branches[i].0),
+                            None => trace!("Took no branch."),
+                        }
+                        self.enter_block(branch);
+                    }
+                    Err(e) => {
+                        self.next_line();
+                        self.fail(e);
+                    }
                 }
             }
-            PassageLine::Branches(branches) => {
-                take_branch(&mut self.config, branches).unwrap();
-                &PassageLine::Continue
+            Op::Random(weights) => {
+                let branch = self.config.rng.choose_weighted(weights);
+                trace!("Picked random branch {:?} of {}.", branch, weights.len());
+                self.enter_block(branch);
             }
-            PassageLine::Goto(goto) => {
-                self.goto(&goto.goto);
-                &PassageLine::Continue
+            Op::Goto(passage) => self.goto(*passage),
+            Op::Call(passage) => self.call(*passage),
+            Op::Return => {
+                if !self.ret() {
+                    // Returning outside of any call ends the story.
+                    self.line = program.passages[self.passage].instructions.len();
+                }
+            }
+            Op::Text(template) => {
+                self.next_line();
+                self.narrate(template);
+            }
+            Op::Dialogue(speakers) => {
+                self.next_line();
+                for speaker in speakers {
+                    let text = match self.render(&speaker.template) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            self.fail(e);
+                            continue;
+                        }
+                    };
+                    let mut speech = speaker.speech.clone();
+                    speech.text = text;
+                    self.emit(Event::Dialogue {
+                        character: speaker.name.to_string(),
+                        speech,
+                    });
+                }
             }
+            Op::Invalid(message) => {
+                self.next_line();
+                self.events.push_back(Event::Error(message.clone()));
+            }
+        }
+    }
+
+    fn end(&mut self) {
+        if !self.ended {
+            self.ended = true;
+            let passage = self.program.passages[self.passage].name;
+            for observer in &mut self.observers {
+                observer.passage_exited(passage);
+                observer.story_ended();
+            }
+        }
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            let passage = self.program.passages[self.passage].name;
+            for observer in &mut self.observers {
+                observer.passage_entered(passage);
+            }
+        }
+    }
+
+    /// Returns the passage and flattened line index of the line that runs next.
+    pub fn position(&self) -> (&str, usize) {
+        (self.program.passages[self.passage].name, self.line)
+    }
+
+    /// Returns the line that runs next, if the current passage has not ended.
+    pub fn current_line(&self) -> Option<&'r PassageLine> {
+        let instructions = &self.program.passages[self.passage].instructions;
+        instructions
+            .get(self.line)
+            .map(|instruction| instruction.line)
+    }
+
+    /// Jumps to the start of a passage, dropping any choices waiting to be made.
+    pub fn jump(&mut self, passage_name: &str) -> Result<(), ValidationError> {
+        let passage = match self.program.passage(passage_name) {
+            Some(passage) => passage,
+            None => return Err(verror!("No passage '{}'.", passage_name)),
+        };
+        self.choices = None;
+        self.goto(passage);
+        Ok(())
+    }
+
+    /// Runs the block at the given index of the current branching line instead of
+    /// evaluating it, or skips the line if there is no block.
+    pub fn take_block(&mut self, block: Option<usize>) -> Result<(), ValidationError> {
+        let line = match self.current_line() {
+            Some(line) if !line.blocks().is_empty() => line,
+            _ => return Err(verror!("The current line does not branch.")),
+        };
+        let count = line.blocks().len();
+        match block {
+            Some(i) if i >= count => Err(verror!("No block {} among {} blocks.", i, count)),
             _ => {
-                // For all others, progress to the next dialog line.
-                self.config.line += 1;
-                line
-            }
-        }
-    }
-    // Processes input from the previous line, and returns the next line.
-    // Say the line 0 is a choice.
-    // First call of next returns the choice, and line should stay at 0.
-    // Don't progress until a valid choice is made.
-    // Then we call next("decision")
-    //
-    // Say the first line is a branch.
-    // Evaluate the branch, modify the line and jump to the appropriate line number.
-    // Then return next.
-    pub fn next(&mut self, input: &str) -> Option<&PassageLine> {
-        let mut result = &PassageLine::Continue;
-        let mut curr_input = input;
-        while result == &PassageLine::Continue {
-            #[cfg(debug_assertions)]
-            {
-                println!(
-                    "{}",
-                    format!("Input {}", curr_input).italic().bright_black()
-                );
-                println!("{}", format!("{:?}", self.config).italic().bright_black());
-            }
-
-            if self.config.line >= self.lines.len() {
-                return None;
-            }
-            result = self.handle_line(curr_input, self.lines[self.config.line]);
-            curr_input = "";
-        }
-        Some(result)
+                trace!("Forced block {:?} of {}.", block, count);
+                self.enter_block(block);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns the next event produced so far, without running any lines.
+    pub fn poll(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    /// Runs the next line, queueing the events it produces.
+    /// While choices are waiting, presents them again instead.
+    /// Returns false once the story has ended.
+    pub fn step(&mut self) -> bool {
+        self.start();
+        if let Some(available) = &self.choices {
+            let choices = Self::choice_ops(&self.program, self.passage, self.line);
+            let lines = available
+                .iter()
+                .map(|&i| Self::line(&choices[i].label))
+                .collect();
+            self.events.push_back(Event::Choices(lines));
+            return true;
+        }
+        // Reaching the end of a called passage implicitly returns.
+        while self.line >= self.program.passages[self.passage].instructions.len() {
+            if !self.ret() {
+                self.end();
+                return false;
+            }
+        }
+        trace!(
+            "{}:{} {:?}",
+            self.program.passages[self.passage].name,
+            self.line,
+            self.current_line()
+        );
+        self.handle_line();
+        true
+    }
+
+    /// Runs the story until something happens, and returns it.
+    pub fn advance(&mut self) -> Event {
+        self.start();
+        loop {
+            if let Some(event) = self.poll() {
+                return event;
+            }
+            if !self.step() {
+                return Event::End;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    /// Parses a story, for the tests of every module.
+    pub(crate) fn story(text: &str) -> Story {
+        serde_yaml::from_str(text).expect(text)
+    }
+
+    /// Returns a config starting at the top of `Start` with no state or characters.
+    pub(crate) fn config() -> Config {
+        config_with("{}")
+    }
+
+    /// Returns the starting config with some of its fields replaced, as in `{state: {gold: 0}}`.
+    pub(crate) fn config_with(fields: &str) -> Config {
+        let mut config: serde_yaml::Mapping =
+            serde_yaml::from_str("{passage: Start, line: 0, state: {}, characters: {}}").unwrap();
+        let fields: serde_yaml::Mapping = serde_yaml::from_str(fields).expect(fields);
+        for (key, value) in fields {
+            config.insert(key, value);
+        }
+        serde_yaml::from_value(serde_yaml::Value::Mapping(config)).unwrap()
+    }
+
+    /// Returns the next event shown to the player, skipping passage and state changes.
+    fn next_shown(runner: &mut Runner) -> Event {
+        loop {
+            match runner.advance() {
+                Event::PassageEntered(_) | Event::StateChanged { .. } => (),
+                event => return event,
+            }
+        }
+    }
+
+    fn line(text: &str) -> Line {
+        Line {
+            text: text.to_string(),
+            id: None,
+        }
+    }
+
+    fn text(text: &str) -> Event {
+        Event::Text(line(text))
+    }
+
+    fn choices(texts: &[&str]) -> Event {
+        Event::Choices(texts.iter().map(|text| line(text)).collect())
+    }
+
+    #[test]
+    fn test_choice_set_and_text() {
+        let story = story(
+            r#"
+Start:
+  - choices:
+      plain: End
+      detailed:
+        goto: End
+        set: { charisma +=: 2 }
+        text: You feel charming.
+End:
+  - The end.
+"#,
+        );
+        let mut config = config_with("{state: {charisma: 0}}");
+        assert!(validate(&config, &story).is_ok());
+
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        assert_eq!(next_shown(&mut runner), choices(&["detailed", "plain"]));
+        runner.choose(0).unwrap();
+        assert_eq!(next_shown(&mut runner), text("You feel charming."));
+        assert_eq!(next_shown(&mut runner), text("The end."));
+        assert_eq!(runner.save().state["charisma"], Value::Int(2));
+    }
+
+    #[test]
+    fn test_visits_and_once_choices() {
+        let story = story(
+            r#"
+Start:
+  - if visits(Start) > 1:
+      - Welcome back.
+  - choices:
+      again: Start
+      only once:
+        goto: Start
+        once: true
+"#,
+        );
+        let mut config = config();
+        assert!(validate(&config, &story).is_ok());
+
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        assert_eq!(next_shown(&mut runner), choices(&["again", "only once"]));
+        runner.choose(1).unwrap();
+        assert_eq!(next_shown(&mut runner), text("Welcome back."));
+        assert_eq!(next_shown(&mut runner), choices(&["again"]));
+        assert!(runner.choose(1).is_err());
+        assert_eq!(next_shown(&mut runner), choices(&["again"]));
+        assert_eq!(runner.save().visits["Start"], 2);
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let story = story(
+            r#"
+Start:
+  - call: Shop
+  - Back at the start.
+  - call: Shop
+Shop:
+  - In the shop.
+  - return:
+  - Unreachable.
+"#,
+        );
+        let mut config = config();
+        assert!(validate(&config, &story).is_ok());
+
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        for expected in &["In the shop.", "Back at the start.", "In the shop."] {
+            assert_eq!(next_shown(&mut runner), text(expected));
+        }
+        assert_eq!(next_shown(&mut runner), Event::End);
+        assert!(runner.save().stack.is_empty());
+    }
+
+    #[test]
+    fn test_branches_and_random() {
+        let story = story(
+            r#"
+Start:
+  - if charisma > 1:
+      - High.
+    else:
+      - Low.
+  - random:
+      - [Heads.]
+      - weight: 0
+        lines: [Never.]
+  - set:
+      charisma =: rand(5, 5)
+  - if charisma == 5:
+      - Five.
+  - The end.
+"#,
+        );
+        let mut config = config_with("{state: {charisma: 2}}");
+        assert!(validate(&config, &story).is_ok());
+
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        for expected in &["High.", "Heads.", "Five.", "The end."] {
+            assert_eq!(next_shown(&mut runner), text(expected));
+        }
+        assert_eq!(next_shown(&mut runner), Event::End);
+        assert_eq!(runner.save().rng.position, 2);
+    }
+
+    #[test]
+    fn test_commands() {
+        let story = story(
+            r#"
+Start:
+  - play_sound: door.wav
+  - shake_screen: 0.5
+  - give: [sword, 2]
+  - The end.
+"#,
+        );
+        let mut config = config();
+        config.commands =
+            serde_yaml::from_str("{play_sound: [string], give: [string, int]}").unwrap();
+        assert!(validate(&config, &story).is_err());
+        config
+            .commands
+            .insert("shake_screen".to_string(), vec![ValueType::Int]);
+        assert!(validate(&config, &story).is_err());
+        config
+            .commands
+            .insert("shake_screen".to_string(), vec![ValueType::Number]);
+        assert!(validate(&config, &story).is_ok());
+        config.commands.remove("shake_screen");
+
+        let mut shakes = vec![];
+        {
+            let mut runner = Runner::new(&mut config, &story).unwrap();
+            assert!(runner.validate().is_err());
+            runner.register_command("shake_screen", vec![ValueType::Number], |command| {
+                shakes.push(command.args.clone())
+            });
+            assert!(runner.validate().is_ok());
+            let command = |name: &str, args: Vec<Value>| {
+                Event::Command(Command {
+                    name: name.to_string(),
+                    args,
+                })
+            };
+            assert_eq!(
+                next_shown(&mut runner),
+                command("play_sound", vec![Value::String("door.wav".to_string())])
+            );
+            assert_eq!(
+                next_shown(&mut runner),
+                command(
+                    "give",
+                    vec![Value::String("sword".to_string()), Value::Int(2)]
+                )
+            );
+            assert_eq!(next_shown(&mut runner), text("The end."));
+        }
+        assert_eq!(shakes, vec![vec![Value::Number(0.5)]]);
+        // Registered commands are not saved with the config.
+        assert!(!config.commands.contains_key("shake_screen"));
+    }
+
+    #[test]
+    fn test_events() {
+        let story = story(
+            r#"
+Start:
+  - Alice (happy): Hi {name}.
+  - choices:
+      leave:
+        goto: End
+        set: { gold +=: 1, name =: Bob }
+End:
+  - call: Shop
+Shop:
+  - goto: Nowhere
+  - return:
+"#,
+        );
+        let mut config = config_with("{state: {gold: 0, name: Ann}}");
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        assert!(runner.choose(0).is_err());
+        assert_eq!(runner.advance(), Event::PassageEntered("Start".to_string()));
+        assert_eq!(
+            runner.advance(),
+            Event::Dialogue {
+                character: "Alice".to_string(),
+                speech: Speech {
+                    text: "Hi Ann.".to_string(),
+                    emotion: Some("happy".to_string()),
+                    ..Speech::default()
+                }
+            }
+        );
+        assert_eq!(runner.advance(), choices(&["leave"]));
+        runner.choose(0).unwrap();
+        let entered = |passage: &str| Event::PassageEntered(passage.to_string());
+        let expected = vec![
+            Event::StateChanged {
+                var: "gold".to_string(),
+                value: Value::Int(1),
+            },
+            Event::StateChanged {
+                var: "name".to_string(),
+                value: Value::String("Bob".to_string()),
+            },
+            entered("End"),
+            entered("Shop"),
+            // Lines that cannot run are reported and skipped.
+            Event::Error("No passage 'Nowhere'.".to_string()),
+            entered("End"),
+            Event::End,
+            Event::End,
+        ];
+        for event in expected {
+            assert_eq!(runner.advance(), event);
+        }
+
+        // Saving writes the state of play back to the config.
+        assert_eq!(runner.get("name"), Some(&Value::String("Bob".to_string())));
+        let saved = runner.save();
+        assert_eq!(saved.state["name"], Value::String("Bob".to_string()));
+        assert_eq!((saved.visits["Shop"], saved.chosen["Start/leave"]), (1, 1));
+        assert_eq!((saved.passage.as_str(), saved.line), ("End", 1));
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+    }
+
+    impl Observer for Recorder {
+        fn passage_entered(&mut self, passage: &str) {
+            self.calls.push(format!("enter {}", passage));
+        }
+
+        fn passage_exited(&mut self, passage: &str) {
+            self.calls.push(format!("exit {}", passage));
+        }
+
+        fn state_changed(&mut self, var: &str, old: &Value, new: &Value) {
+            self.calls.push(format!("{}: {} -> {}", var, old, new));
+        }
+
+        fn choice_made(&mut self, passage: &str, choice: &str) {
+            self.calls
+                .push(format!("choose {}", choice_key(passage, choice)));
+        }
+
+        fn story_ended(&mut self) {
+            self.calls.push("end".to_string());
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let story = story(
+            r#"
+Start:
+  - choices:
+      leave:
+        goto: End
+        set: { gold +=: 1 }
+End:
+  - call: Shop
+Shop:
+  - set: { gold -=: 0 }
+  - return:
+"#,
+        );
+        let mut config = config_with("{state: {gold: 0}}");
+        let mut recorder = Recorder::default();
+        {
+            let mut runner = Runner::new(&mut config, &story).unwrap();
+            runner.observe(&mut recorder);
+            assert!(matches!(runner.advance(), Event::PassageEntered(_)));
+            assert!(matches!(runner.advance(), Event::Choices(_)));
+            runner.choose(0).unwrap();
+            while runner.advance() != Event::End {}
+            runner.advance();
+        }
+        let expected = vec![
+            "enter Start",
+            "choose Start/leave",
+            "gold: 0 -> 1",
+            "exit Start",
+            "enter End",
+            "exit End",
+            "enter Shop",
+            "exit Shop",
+            "enter End",
+            "exit End",
+            "end",
+        ];
+        assert_eq!(recorder.calls, expected);
+    }
+
+    #[test]
+    fn test_step_and_take_block() {
+        let story = story(
+            r#"
+Start:
+  - if gold > 100:
+      - Rich.
+    else:
+      - Poor.
+  - The end.
+Late:
+  - Late game.
+"#,
+        );
+        let mut config = config_with("{state: {gold: 0}}");
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        assert_eq!(runner.position(), ("Start", 0));
+        assert!(runner.take_block(Some(2)).is_err());
+        runner.take_block(Some(0)).unwrap();
+        assert_eq!(
+            runner.current_line(),
+            Some(&PassageLine::Text("Rich.".to_string()))
+        );
+        assert!(runner.take_block(None).is_err());
+        assert!(runner.step());
+        assert_eq!(
+            runner.poll(),
+            Some(Event::PassageEntered("Start".to_string()))
+        );
+        assert_eq!(runner.poll(), Some(text("Rich.")));
+        assert_eq!(runner.poll(), None);
+
+        assert!(runner.jump("Nowhere").is_err());
+        runner.jump("Late").unwrap();
+        assert_eq!(next_shown(&mut runner), text("Late game."));
+        assert!(!runner.step());
+        assert_eq!(runner.save().visits["Late"], 1);
+
+        // The debugger sets state by name, which may not exist.
+        let set = |text: &str| serde_yaml::from_str::<State>(text).unwrap();
+        assert!(runner.set(&set("{nope =: 5}")).is_err());
+        runner.set(&set("{gold +=: 5}")).unwrap();
+        assert_eq!(runner.save().state["gold"], Value::Int(5));
+    }
+
+    #[test]
+    fn test_history() {
+        let story = story(
+            r#"
+Start:
+  - Alice: Hello.
+  - choices:
+      wave:
+        goto: End
+        text: You wave.
+End:
+  - Bye.
+"#,
+        );
+        let play = |config: &mut Config| {
+            let mut runner = Runner::new(config, &story).unwrap();
+            assert!(matches!(next_shown(&mut runner), Event::Dialogue { .. }));
+            assert!(matches!(next_shown(&mut runner), Event::Choices(_)));
+            runner.choose(0).unwrap();
+            while runner.advance() != Event::End {}
+            runner.save();
+            runner.history().clone()
+        };
+        let mut config = config();
+        let speech = Speech {
+            text: "Hello.".to_string(),
+            ..Speech::default()
+        };
+        let expected: VecDeque<Record> = vec![
+            Record::Dialogue {
+                character: "Alice".to_string(),
+                speech,
+            },
+            Record::Choice("wave".to_string()),
+            Record::Text("You wave.".to_string()),
+            Record::Text("Bye.".to_string()),
+        ]
+        .into();
+        assert_eq!(play(&mut config), expected);
+
+        // The history is saved along with the rest of the state.
+        let saved: Config = serde_yaml::from_str(&serde_yaml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(saved.history, expected);
+
+        // Only the latest records are kept.
+        let mut config = config_with("{history_limit: 2}");
+        let latest: VecDeque<Record> = expected.into_iter().skip(2).collect();
+        assert_eq!(play(&mut config), latest);
+    }
+
+    #[cfg(feature = "locale")]
+    #[test]
+    fn test_locale() {
+        let story = story(
+            r#"
+Start:
+  - Hello, {name}. @line:hello
+  - Alice: Hi! @line:hi
+  - choices:
+      Wave @line:wave:
+        goto: Start
+        once: true
+        text: You wave. @line:waved
+      Leave: End
+End:
+  - if chosen(Start, wave) > 0:
+      - Bye. @line:bye
+"#,
+        );
+        let mut config = config_with("{state: {name: Bob}, characters: {Alice: {}}}");
+        assert!(validate(&config, &story).is_ok());
+        let locale = Locale::from_csv(
+            "id,source,translation\nhello,\"Hello, {name}.\",\"Bonjour, {name}.\"\nwave,Wave,Saluer\nwaved,You wave.,Vous saluez.\nbye,Bye.,\n",
+        )
+        .unwrap();
+
+        let with_id = |text: &str, id: &str| Line {
+            text: text.to_string(),
+            id: Some(id.to_string()),
+        };
+
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        runner.localize(&locale);
+        let hello = Event::Text(with_id("Bonjour, Bob.", "hello"));
+        assert_eq!(next_shown(&mut runner), hello);
+        match next_shown(&mut runner) {
+            Event::Dialogue { speech, .. } => {
+                assert_eq!(speech.text, "Hi!");
+                assert_eq!(speech.id.as_deref(), Some("hi"));
+            }
+            event => panic!("Expected dialogue, not {:?}", event),
+        }
+        assert_eq!(
+            next_shown(&mut runner),
+            Event::Choices(vec![line("Leave"), with_id("Saluer", "wave")])
+        );
+        runner.choose(1).unwrap();
+        assert_eq!(
+            next_shown(&mut runner),
+            Event::Text(with_id("Vous saluez.", "waved"))
+        );
+        assert_eq!(next_shown(&mut runner), hello);
+        next_shown(&mut runner);
+        // Choices are counted by their IDs, whatever language they are shown in.
+        assert_eq!(next_shown(&mut runner), choices(&["Leave"]));
+        runner.choose(0).unwrap();
+        assert_eq!(next_shown(&mut runner), Event::Text(with_id("Bye.", "bye")));
+        assert_eq!(runner.save().chosen["Start/wave"], 1);
+        assert!(runner
+            .history()
+            .contains(&Record::Choice("Saluer".to_string())));
+    }
+
+    #[test]
+    fn test_voice() {
+        let story = story(
+            r#"
+Start:
+  - Alice: Hi! @line:hi
+  - Alice: {text: Again!, voice: again.ogg}
+  - Alice: No ID.
+  - Bob: Hey! @line:hey
+"#,
+        );
+        let mut config = config_with("{characters: {Alice: {voice: 'alice/{id}.ogg'}, Bob: {}}}");
+        assert!(validate(&config, &story).is_ok());
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        let mut voices = vec![];
+        while let Event::Dialogue { speech, .. } = next_shown(&mut runner) {
+            voices.push(speech.voice);
+        }
+        let expected = vec![Some("alice/hi.ogg"), Some("again.ogg"), None, None];
+        assert_eq!(
+            voices,
+            expected
+                .into_iter()
+                .map(|voice| voice.map(str::to_string))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_taken_branch_skips_the_others() {
+        let story = story(
+            r#"
+Start:
+  - if visits(Start) == 1:
+      - First.
+    else:
+      - Again.
+  - The end.
+"#,
+        );
+        let mut config = config();
+
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        for expected in &["First.", "The end."] {
+            assert_eq!(next_shown(&mut runner), text(expected));
+        }
+        assert_eq!(next_shown(&mut runner), Event::End);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let story = story(
+            r#"
+Start:
+  - set: {mood =: sleepy}
+  - if mood > 1:
+      - Bigger.
+  - Still here.
+"#,
+        );
+
+        // A save naming a passage the story does not have cannot be resumed.
+        let mut config = config_with("{passage: Missing}");
+        assert!(Runner::new(&mut config, &story).is_err());
+
+        // Lines that fail as they run are reported and skipped.
+        let mut config =
+            config_with("{declarations: {mood: {type: enum, variants: [calm, angry]}}}");
+        let mut runner = Runner::new(&mut config, &story).unwrap();
+        assert!(matches!(next_shown(&mut runner), Event::Error(_)));
+        assert!(matches!(next_shown(&mut runner), Event::Error(_)));
+        assert_eq!(next_shown(&mut runner), text("Still here."));
+        assert_eq!(next_shown(&mut runner), Event::End);
     }
 }
@@ -1,34 +1,264 @@
+use crate::declaration::{Declaration, ValueType};
+use crate::history::Record;
+use crate::locale::split_id;
+use crate::rng::Rng;
 use crate::value::Value;
 // use linked_hash_map::LinkedHashMap;
 use linear_map::LinearMap;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
 
 pub type Map<K, V> = BTreeMap<K, V>;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// Text styles a character's name may be displayed with.
+pub const STYLES: &[&str] = &[
+    "bold",
+    "dimmed",
+    "italic",
+    "underline",
+    "blink",
+    "reversed",
+    "hidden",
+    "strikethrough",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct CharacterData {
+    #[serde(default)]
     pub description: String,
+    /// Name shown to the player. Defaults to the character's key in the story.
+    pub name: Option<String>,
+    /// Colour of the name, such as `red` or `bright blue`.
+    pub color: Option<String>,
+    /// Styles of the name from `STYLES`.
+    #[serde(default)]
+    pub style: Vec<String>,
+    /// Text shown before each of the character's lines.
+    #[serde(default)]
+    pub prefix: String,
+    /// Path to the character's portrait image.
+    pub portrait: Option<String>,
+    /// Emotions the character's dialogue may be tagged with.
+    #[serde(default)]
+    pub emotions: Vec<String>,
+    /// Path of the voice-over clips of the character's lines, where `{id}` stands for
+    /// the ID of the line, as in `voices/alex/{id}.ogg`.
+    pub voice: Option<String>,
+}
+
+impl CharacterData {
+    /// Returns the name to display for the character stored under `key`.
+    pub fn display_name<'a>(&'a self, key: &'a str) -> &'a str {
+        self.name.as_deref().unwrap_or(key)
+    }
+
+    /// Returns the voice-over clip of the character's line with the given ID.
+    pub fn voice_clip(&self, id: &str) -> Option<String> {
+        self.voice.as_ref().map(|voice| voice.replace("{id}", id))
+    }
 }
 
 pub type Characters = Map<String, CharacterData>;
 pub type State = Map<String, Value>;
+/// Argument types of the commands handled by the host, by command name.
+pub type Commands = Map<String, Vec<ValueType>>;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub passage: String,
     pub line: usize,
     pub state: State,
     pub characters: Characters,
+    /// Types and constraints of state variables.
+    /// Variables without a declaration take the type of their initial value.
+    #[serde(default)]
+    pub declarations: Map<String, Declaration>,
+    /// Commands the story may run, declared with the story so that it can be validated.
+    /// Commands registered at runtime with `Runner::register_command` are kept by the
+    /// runner instead, since the config is saved with the player's progress.
+    #[serde(default)]
+    pub commands: Commands,
+    /// Number of times each passage has been entered.
+    #[serde(default)]
+    pub visits: Map<String, usize>,
+    /// Number of times each choice has been made, keyed by `choice_key`.
+    #[serde(default)]
+    pub chosen: Map<String, usize>,
+    /// Return addresses of the passages currently being called.
+    #[serde(default)]
+    pub stack: Vec<StackFrame>,
+    /// Random number generator for random lines and `rand()`, saved with the
+    /// config so that playthroughs can be replayed.
+    #[serde(default)]
+    pub rng: Rng,
+    /// Number of the latest lines and choices kept in the history, which is saved with
+    /// the config. Older records are dropped, and zero keeps no history at all.
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    /// Lines shown and choices made so far, oldest first.
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub history: VecDeque<Record>,
+}
+
+fn default_history_limit() -> usize {
+    200
+}
+
+/// Where to resume once a called passage returns.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackFrame {
+    pub passage: String,
+    pub line: usize,
+}
+
+impl Config {
+    /// Returns the declaration of a state variable, inferring it from the variable's
+    /// initial value if it was not declared.
+    pub fn declaration(&self, var: &str) -> Option<Declaration> {
+        match self.declarations.get(var) {
+            Some(declaration) => Some(declaration.clone()),
+            None => Declaration::infer(self.state.get(var)?),
+        }
+    }
+
+    /// Initializes declared state variables that have no value yet to their defaults.
+    pub fn init_state(&mut self) {
+        for (var, declaration) in &self.declarations {
+            if !self.state.contains_key(var) {
+                self.state.insert(var.clone(), declaration.default_value());
+            }
+        }
+    }
 }
 
-pub type Dialogue = Map<String, String>;
+/// Key identifying a choice in `Config::chosen`.
+pub fn choice_key(passage: &str, choice: &str) -> String {
+    format!("{}/{}", passage, choice)
+}
+
+/// A line of dialogue with presentation attributes for the front end.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Speech {
+    pub text: String,
+    /// Expression of the speaker, one of their character's `emotions`.
+    pub emotion: Option<String>,
+    /// Voice-over clip to play with the line, instead of the one found by its ID.
+    pub voice: Option<String>,
+    /// Where the speaker stands on screen, such as `left` or `right`.
+    pub position: Option<String>,
+    /// ID of the line, if not given at the end of its text.
+    pub id: Option<String>,
+}
+
+/// What a character says: plain text, or text with attributes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DialogueText {
+    Text(String),
+    Speech(Speech),
+}
+
+impl DialogueText {
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Text(text) => text,
+            Self::Speech(speech) => &speech.text,
+        }
+    }
+
+    /// ID of the line, given either at the end of its text or as a field.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => split_id(text).1,
+            Self::Speech(speech) => speech.id.as_deref().or_else(|| split_id(&speech.text).1),
+        }
+    }
+
+    pub fn emotion(&self) -> Option<&str> {
+        match self {
+            Self::Text(_) => None,
+            Self::Speech(speech) => speech.emotion.as_deref(),
+        }
+    }
+}
+
+/// Dialogue keyed by speaker. Keys may carry an emotion, as in `Person1 (angry)`.
+pub type Dialogue = Map<String, DialogueText>;
+
+/// Splits a dialogue key such as `Person1 (angry)` into the character name and emotion.
+pub fn split_speaker(key: &str) -> (&str, Option<&str>) {
+    let key = key.trim();
+    if let (Some(open), true) = (key.find('('), key.ends_with(')')) {
+        return (
+            key[..open].trim_end(),
+            Some(key[open + 1..key.len() - 1].trim()),
+        );
+    }
+    (key, None)
+}
 
 pub type Branches<T> = LinearMap<String, Vec<T>>;
 
+/// A choice that applies state modifications (and optionally echoes text) before jumping.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChoiceCmd {
+    pub goto: String,
+    #[serde(default)]
+    pub set: State,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// If true, the choice is hidden once it has been made.
+    #[serde(default)]
+    pub once: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Choice {
+    PassageName(String),
+    ChoiceCmd(ChoiceCmd),
+}
+
+impl Choice {
+    /// Name of the passage to jump to when this choice is made.
+    pub fn passage(&self) -> &str {
+        match self {
+            Self::PassageName(passage) => passage,
+            Self::ChoiceCmd(cmd) => &cmd.goto,
+        }
+    }
+
+    /// State modifications applied when this choice is made.
+    pub fn set(&self) -> Option<&State> {
+        match self {
+            Self::PassageName(_) => None,
+            Self::ChoiceCmd(cmd) => Some(&cmd.set),
+        }
+    }
+
+    /// Whether this choice disappears after being made.
+    pub fn once(&self) -> bool {
+        match self {
+            Self::PassageName(_) => false,
+            Self::ChoiceCmd(cmd) => cmd.once,
+        }
+    }
+
+    /// Text echoed back when this choice is made.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Self::PassageName(_) => None,
+            Self::ChoiceCmd(cmd) => cmd.text.as_deref(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Choices {
-    pub choices: Map<String, String>,
+    pub choices: Map<String, Choice>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -36,22 +266,157 @@ pub struct Goto {
     pub goto: String,
 }
 
+/// Jumps to a passage, returning to the next line once it ends or hits a `return`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Call {
+    pub call: String,
+}
+
+/// Returns from the passage entered by the last `call`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Return {
+    #[serde(rename = "return")]
+    pub value: (),
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SetCmd {
     pub set: State,
 }
 
+/// One of the possible outcomes of a `random` line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RandomBranch {
+    Lines(Vec<PassageLine>),
+    Weighted {
+        weight: f64,
+        lines: Vec<PassageLine>,
+    },
+}
+
+impl RandomBranch {
+    pub fn weight(&self) -> f64 {
+        match self {
+            Self::Lines(_) => 1.,
+            Self::Weighted { weight, .. } => *weight,
+        }
+    }
+
+    pub fn lines(&self) -> &Passage {
+        match self {
+            Self::Lines(lines) => lines,
+            Self::Weighted { lines, .. } => lines,
+        }
+    }
+}
+
+/// A line handled by the host, such as `play_sound: door.wav` or `shake_screen: 0.5`.
+/// Several arguments are written as a list. A command whose only argument is a string
+/// reads as dialogue, and is told apart by its name being declared in `Config::commands`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Map<String, Value>", into = "Map<String, Value>")]
+pub struct Command {
+    pub name: String,
+    pub args: Vec<Value>,
+}
+
+impl Command {
+    pub fn new(name: &str, value: Value) -> Self {
+        let args = match value {
+            Value::None => vec![],
+            Value::List(args) => args,
+            value => vec![value],
+        };
+        Self {
+            name: name.to_string(),
+            args,
+        }
+    }
+
+    /// Reads a dialogue line as a command if its speaker is a command.
+    pub fn from_dialogue(dialogue: &Dialogue, is_command: impl Fn(&str) -> bool) -> Option<Self> {
+        let mut entries = dialogue.iter();
+        match (entries.next(), entries.next()) {
+            (Some((name, DialogueText::Text(text))), None) if is_command(name) => {
+                Some(Self::new(name, Value::String(text.clone())))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<Map<String, Value>> for Command {
+    type Error = String;
+
+    fn try_from(map: Map<String, Value>) -> Result<Self, Self::Error> {
+        let mut entries = map.into_iter();
+        match (entries.next(), entries.next()) {
+            // Strings are left to dialogue, and branch keys like `if x` or `else` to branches.
+            (Some((_, Value::String(_))), None) => Err("Expected a non-string argument.".into()),
+            (Some((name, value)), None)
+                if name != "else" && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+            {
+                Ok(Self::new(&name, value))
+            }
+            _ => Err("Expected a single command name.".into()),
+        }
+    }
+}
+
+impl From<Command> for Map<String, Value> {
+    fn from(command: Command) -> Self {
+        let mut args = command.args;
+        let value = match args.len() {
+            0 => Value::None,
+            1 => args.remove(0),
+            _ => Value::List(args),
+        };
+        let mut map = Map::new();
+        map.insert(command.name, value);
+        map
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(Value::to_string).collect();
+        write!(f, "{}: {}", self.name, args.join(", "))
+    }
+}
+
+/// Runs one of several sequences of lines, picked at random by weight.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Random {
+    pub random: Vec<RandomBranch>,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum PassageLine {
-    Branches(Branches<PassageLine>),
+    // Must come before branches, whose lines would otherwise accept random branches.
+    Random(Random),
     Choices(Choices),
     Goto(Goto),
+    Call(Call),
+    Return(Return),
+    // Must come after return, which would otherwise parse as a command without arguments.
+    Command(Command),
+    Branches(Branches<PassageLine>),
     Text(String),
     SetCmd(SetCmd),
     Dialogue(Dialogue),
-    Continue,
-    InvalidChoice,
+}
+
+impl PassageLine {
+    /// Returns the nested blocks of lines of a branching line, in order.
+    pub fn blocks(&self) -> Vec<&Passage> {
+        match self {
+            Self::Branches(branches) => branches.values().collect(),
+            Self::Random(random) => random.random.iter().map(RandomBranch::lines).collect(),
+            _ => vec![],
+        }
+    }
 }
 
 pub type Passage = Vec<PassageLine>;
@@ -1,30 +1,232 @@
 use crate::comparator::Comparator;
 use crate::conditional::Conditional;
+use crate::declaration::{Declaration, ValueType};
 use crate::error::ValidationError;
+use crate::interpolate::expressions;
+use crate::locale::{line_key, lines, split_id, Locale};
+use crate::operand::{is_call, Operand};
 use crate::operator::Operator;
 use crate::state::StateMod;
-use crate::structs::{Branches, Choices, Config, Map, Passage, PassageLine, State, Story};
+use crate::structs::{
+    split_speaker, Branches, CharacterData, Choices, Command, Config, Dialogue, DialogueText, Map,
+    Passage, PassageLine, Random, Speech, State, Story, STYLES,
+};
 use crate::value::Value;
+use colored::Color;
+#[cfg(feature = "html")]
 use html_parser::Dom;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-/// Validate text to guarantee valid HTML.
-fn validate_text(text: &str) -> Result<(), ValidationError> {
-    match Dom::parse(text) {
-        Err(e) => Err(verror!("Text error: {}", e)),
-        Ok(_) => Ok(()),
+/// Validate text to guarantee valid HTML and valid interpolated expressions.
+fn validate_text(config: &Config, story: &Story, text: &str) -> Result<(), ValidationError> {
+    #[cfg(feature = "html")]
+    if let Err(e) = Dom::parse(text) {
+        return Err(verror!("Text error: {}", e));
+    }
+    for expression in expressions(text)? {
+        validate_operand(config, story, &Operand::parse(expression)?)?;
+    }
+    Ok(())
+}
+
+/// Voice-over clips of the dialogue, collected as the story is validated.
+#[derive(Default)]
+struct Voices {
+    /// Passage whose dialogue is being validated.
+    passage: String,
+    clips: BTreeSet<PathBuf>,
+    /// Lines of dialogue without a clip.
+    missing: Vec<String>,
+}
+
+impl Voices {
+    /// Records the clip of a character's line, or the line as missing one.
+    fn add(&mut self, name: &str, character: &CharacterData, text: &DialogueText) {
+        let clip = match text {
+            DialogueText::Speech(Speech {
+                voice: Some(voice), ..
+            }) => Some(voice.clone()),
+            _ => text.id().and_then(|id| character.voice_clip(id)),
+        };
+        match clip {
+            Some(clip) => {
+                self.clips.insert(PathBuf::from(clip));
+            }
+            None if character.voice.is_some() => self.missing.push(format!(
+                "Passage '{}': {} has no ID to find the audio of '{}' by.",
+                self.passage,
+                name,
+                text.text()
+            )),
+            None => self.missing.push(format!(
+                "Passage '{}': {} has no voice for '{}'.",
+                self.passage,
+                name,
+                text.text()
+            )),
+        }
     }
 }
 
 /// Validate that the dialogue contains valid text and configured characters only.
 fn validate_dialogue(
     config: &Config,
-    dialogue: &Map<String, String>,
+    story: &Story,
+    dialogue: &Dialogue,
+    voices: &mut Voices,
 ) -> Result<(), ValidationError> {
-    for (name, text) in dialogue {
-        if !config.characters.contains_key(name) {
-            return Err(verror!("Undefined character name: {}", name));
+    for (key, text) in dialogue {
+        let (name, key_emotion) = split_speaker(key);
+        let character = match config.characters.get(name) {
+            Some(character) => character,
+            None => return Err(verror!("Undefined character name: {}", name)),
+        };
+        let emotion = match (key_emotion, text.emotion()) {
+            (Some(_), Some(_)) => {
+                return Err(verror!("Dialogue of '{}' has two emotions.", name));
+            }
+            (emotion, None) | (None, emotion) => emotion,
+        };
+        if let Some(emotion) = emotion {
+            if !character.emotions.iter().any(|e| e == emotion) {
+                return Err(verror!(
+                    "Character '{}' has no emotion '{}'.",
+                    name,
+                    emotion
+                ));
+            }
         }
-        validate_text(&text)?;
+        validate_text(config, story, text.text())?;
+        voices.add(name, character, text);
+    }
+    Ok(())
+}
+
+/// Validates that a command is declared and that its arguments match its signature.
+fn validate_command(config: &Config, command: &Command) -> Result<(), ValidationError> {
+    let signature = match config.commands.get(&command.name) {
+        Some(signature) => signature,
+        // Unquoted dialogue such as `Alice: 42` reads as a command.
+        None if config.characters.contains_key(&command.name) => {
+            return Err(verror!(
+                "Dialogue of '{}' must be text, not '{}'. Quote it to show it as is.",
+                command.name,
+                command
+                    .args
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+        None => return Err(verror!("Unknown command '{}'.", command.name)),
+    };
+    if signature.len() != command.args.len() {
+        return Err(verror!(
+            "Command '{}' takes {} arguments, not {}.",
+            command.name,
+            signature.len(),
+            command.args.len()
+        ));
+    }
+    for (i, (value_type, arg)) in signature.iter().zip(&command.args).enumerate() {
+        if !value_type.accepts(arg) {
+            return Err(verror!(
+                "Argument {} of '{}' should be a {:?}, not {:?}.",
+                i + 1,
+                command.name,
+                value_type,
+                arg
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Validates an operand, returning the declaration its values satisfy.
+/// Builtin functions must only reference passages in the story.
+fn validate_operand(
+    config: &Config,
+    story: &Story,
+    operand: &Operand,
+) -> Result<Declaration, ValidationError> {
+    match operand {
+        Operand::Var(var) => validate_state_var(config, var),
+        Operand::Visits(passage_name) => {
+            validate_goto(story, passage_name)?;
+            Ok(Declaration::int(Some(0.), None))
+        }
+        Operand::Chosen(passage_name, choice) => {
+            validate_goto(story, passage_name)?;
+            validate_chosen(&story[*passage_name], passage_name, choice)?;
+            Ok(Declaration::int(Some(0.), None))
+        }
+        Operand::Rand(min, max) => Ok(Declaration::int(Some(*min as f64), Some(*max as f64))),
+        Operand::Len(var) => {
+            validate_collection(var, &validate_state_var(config, var)?)?;
+            Ok(Declaration::int(Some(0.), None))
+        }
+    }
+}
+
+/// Collects the texts of the choices in some lines, including nested blocks.
+fn choice_texts<'s>(lines: &'s [PassageLine], texts: &mut Vec<&'s str>) {
+    for line in lines {
+        if let PassageLine::Choices(choices) = line {
+            texts.extend(choices.choices.keys().map(String::as_str));
+        }
+        for block in line.blocks() {
+            choice_texts(block, texts);
+        }
+    }
+}
+
+/// Validates that a choice counted by `chosen` is in the passage, referred to by its ID
+/// if it has one.
+fn validate_chosen(
+    passage: &Passage,
+    passage_name: &str,
+    choice: &str,
+) -> Result<(), ValidationError> {
+    let mut texts = vec![];
+    choice_texts(passage, &mut texts);
+    if texts.iter().any(|text| line_key(text) == choice) {
+        return Ok(());
+    }
+    match texts.iter().find_map(|text| match split_id(text) {
+        (source, Some(id)) if source == choice => Some(id),
+        _ => None,
+    }) {
+        Some(id) => Err(verror!(
+            "Choice '{}' has the ID '{}', by which it is counted.",
+            choice,
+            id
+        )),
+        None => Err(verror!(
+            "Passage '{}' has no choice '{}'.",
+            passage_name,
+            choice
+        )),
+    }
+}
+
+/// Validates the weights and lines of a random line.
+fn validate_random(
+    config: &Config,
+    story: &Story,
+    random: &Random,
+    voices: &mut Voices,
+) -> Result<(), ValidationError> {
+    for branch in &random.random {
+        if branch.weight().is_nan() || branch.weight() < 0. {
+            return Err(verror!(
+                "Random weights must not be negative, not {}.",
+                branch.weight()
+            ));
+        }
+        validate_passage(config, story, branch.lines(), voices)?;
     }
     Ok(())
 }
@@ -34,17 +236,19 @@ fn validate_conditional(
     config: &Config,
     story: &Story,
     branches: &Branches<PassageLine>,
+    voices: &mut Voices,
 ) -> Result<(), ValidationError> {
     for (expression, lines) in branches {
         if expression != "else" {
             let cond = Conditional::parse(expression)?;
-            if !config.state.contains_key(cond.var) {
-                return Err(verror!("No such state '{}'.", cond.var));
-            }
-            cond.eval(&config.state)?;
-            validate_cmp(&cond.val, &config.state[cond.var], cond.cmp)?;
+            let declaration = validate_operand(config, story, &cond.lhs)?;
+            let var = match cond.lhs {
+                Operand::Var(var) => var,
+                _ => expression,
+            };
+            validate_cmp(var, &declaration, &cond.val, cond.cmp)?;
         }
-        validate_passage(config, story, lines)?;
+        validate_passage(config, story, lines, voices)?;
     }
     Ok(())
 }
@@ -53,14 +257,23 @@ fn validate_line(
     config: &Config,
     story: &Story,
     line: &PassageLine,
+    voices: &mut Voices,
 ) -> Result<(), ValidationError> {
     match &line {
-        PassageLine::Dialogue(dialogue) => validate_dialogue(config, dialogue),
-        PassageLine::Text(text) => validate_text(text),
-        PassageLine::Branches(cond) => validate_conditional(config, story, cond),
-        PassageLine::Choices(choices) => validate_choices(story, choices),
+        PassageLine::Dialogue(dialogue) => {
+            match Command::from_dialogue(dialogue, |name| config.commands.contains_key(name)) {
+                Some(command) => validate_command(config, &command),
+                None => validate_dialogue(config, story, dialogue, voices),
+            }
+        }
+        PassageLine::Command(command) => validate_command(config, command),
+        PassageLine::Text(text) => validate_text(config, story, text),
+        PassageLine::Branches(cond) => validate_conditional(config, story, cond, voices),
+        PassageLine::Random(random) => validate_random(config, story, random, voices),
+        PassageLine::Choices(choices) => validate_choices(config, story, choices),
         PassageLine::Goto(goto) => validate_goto(story, &goto.goto),
-        PassageLine::SetCmd(cmd) => validate_state(config, &cmd.set),
+        PassageLine::Call(call) => validate_goto(story, &call.call),
+        PassageLine::SetCmd(cmd) => validate_state(config, story, &cmd.set),
         _ => Ok(()),
     }
 }
@@ -70,80 +283,217 @@ fn validate_passage(
     config: &Config,
     story: &Story,
     lines: &Passage,
+    voices: &mut Voices,
 ) -> Result<(), ValidationError> {
     for (i, line) in lines.iter().enumerate() {
-        if let Err(e) = validate_line(config, story, line) {
+        if let Err(e) = validate_line(config, story, line, voices) {
             return Err(verror!("Line {}: {}", i + 1, e));
         }
     }
     Ok(())
 }
 
-/// Validates an operator on a given value.
-/// Any value supports assignment, but only Numbers can be added or subtracted.
-fn validate_op(v1: &Value, v2: &Value, op: Operator) -> Result<(), ValidationError> {
+fn validate_collection(var: &str, declaration: &Declaration) -> Result<(), ValidationError> {
+    if declaration.value_type.is_collection() {
+        Ok(())
+    } else {
+        Err(verror!(
+            "'{}' is a {:?}, not a list or set.",
+            var,
+            declaration.value_type
+        ))
+    }
+}
+
+/// Validates that an item of the given declaration can be stored in a collection.
+fn validate_item(
+    var: &str,
+    declaration: &Declaration,
+    item: &Declaration,
+) -> Result<(), ValidationError> {
+    validate_collection(var, declaration)?;
+    if item.value_type.is_collection() {
+        return Err(verror!(
+            "Lists and sets cannot contain other lists or sets."
+        ));
+    }
+    match declaration.items {
+        Some(items) if !items.can_hold(item.value_type) => Err(verror!(
+            "'{}' contains items of type {:?}, not {:?}.",
+            var,
+            items,
+            item.value_type
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Validates an operator between a declared variable and a value of the given declaration.
+/// Any value supports assignment, but only numbers can be added or subtracted.
+/// Floats may not be assigned, added or subtracted to integer variables.
+fn validate_op(
+    var: &str,
+    declaration: &Declaration,
+    value: &Declaration,
+    op: Operator,
+) -> Result<(), ValidationError> {
+    if declaration.value_type == ValueType::Int && value.value_type == ValueType::Number {
+        return Err(verror!(
+            "A float is used with integer variable '{}'; use an integer value instead.",
+            var
+        ));
+    }
     match op {
+        Operator::INSERT | Operator::REMOVE => validate_item(var, declaration, value),
+        Operator::CLEAR => validate_collection(var, declaration),
         Operator::SET => {
-            if v1.same_type(v2) {
+            if declaration.value_type.can_hold(value.value_type) {
                 Ok(())
             } else {
                 Err(verror!(
                     "Operators require operands of the same type, not {:?} and {:?}",
-                    v1,
-                    v2
+                    declaration.value_type,
+                    value.value_type
+                ))
+            }
+        }
+        Operator::ADD | Operator::SUB => {
+            if declaration.value_type.is_numeric() && value.value_type.is_numeric() {
+                Ok(())
+            } else {
+                Err(verror!(
+                    "Comparators '+,-' can only be used on two numbers, not {:?} and {:?}.",
+                    declaration.value_type,
+                    value.value_type
                 ))
             }
         }
-        Operator::ADD | Operator::SUB => match (v1, v2) {
-            (Value::Number(_), Value::Number(_)) => Ok(()),
-            _ => Err(verror!(
-                "Comparators '+,-' can only be used on two numbers, not {:?} and {:?}.",
-                v1,
-                v2
-            )),
-        },
     }
 }
 
-/// Validates an comparator on a given value.
-/// Any value supports assignment, but only Numbers can be added or subtracted.
-fn validate_cmp(v1: &Value, v2: &Value, cmp: Comparator) -> Result<(), ValidationError> {
+/// Validates a comparator between a declared variable and a value.
+/// Any values can be checked for equality, but only Numbers can be ordered.
+fn validate_cmp(
+    var: &str,
+    declaration: &Declaration,
+    value: &Value,
+    cmp: Comparator,
+) -> Result<(), ValidationError> {
     match cmp {
         Comparator::EQ | Comparator::NEQ => {
-            if v1.same_type(v2) {
-                Ok(())
-            } else {
+            let numeric = declaration.value_type.is_numeric() && value.as_f64().is_some();
+            if !declaration.value_type.accepts(value) && !numeric {
                 Err(verror!(
                     "Comparisons require values of the same type, not {:?} and {:?}",
-                    v1,
-                    v2
+                    declaration.value_type,
+                    value
                 ))
+            } else if declaration.value_type == ValueType::Enum {
+                declaration.check(value)
+            } else {
+                Ok(())
             }
         }
-        Comparator::LT | Comparator::LEQ | Comparator::GT | Comparator::GEQ => match (v1, v2) {
-            (Value::Number(_), Value::Number(_)) => Ok(()),
-            _ => Err(verror!(
+        Comparator::HAS => match Declaration::infer(value) {
+            Some(item) => validate_item(var, declaration, &item),
+            None => Err(verror!("Cannot check whether a list contains {:?}.", value)),
+        },
+        Comparator::LT | Comparator::LEQ | Comparator::GT | Comparator::GEQ => {
+            match (declaration.value_type.is_numeric(), value.as_f64()) {
+                (true, Some(_)) => Ok(()),
+                _ => Err(verror!(
                 "Comparators '>,>=,<,<=' can only be used between two numbers, not {:?} and {:?}.",
-                v1,
-                v2
+                declaration.value_type,
+                value
             )),
-        },
+            }
+        }
     }
 }
 
-fn validate_state_var(config: &Config, var: &str) -> Result<(), ValidationError> {
-    if !config.state.contains_key(var) {
-        Err(verror!("No state variable named '{}'", var))
-    } else {
-        Ok(())
+fn validate_state_var(config: &Config, var: &str) -> Result<Declaration, ValidationError> {
+    match config.declaration(var) {
+        Some(declaration) => Ok(declaration),
+        None => Err(verror!("No state variable named '{}'", var)),
     }
 }
+
 /// Validates the state only contains configured keys.
-fn validate_state(config: &Config, state: &State) -> Result<(), ValidationError> {
+fn validate_state(config: &Config, story: &Story, state: &State) -> Result<(), ValidationError> {
     for (key, value) in state {
         let smod = StateMod::parse(key)?;
-        validate_state_var(config, smod.var)?;
-        validate_op(&config.state[smod.var], value, smod.op)?;
+        let declaration = validate_state_var(config, smod.var)?;
+        match value {
+            _ if smod.op == Operator::CLEAR => validate_collection(smod.var, &declaration)?,
+            Value::String(text) if is_call(text) => {
+                let operand = Operand::parse(text)?;
+                let value = validate_operand(config, story, &operand)?;
+                validate_op(smod.var, &declaration, &value, smod.op)?;
+            }
+            _ => match Declaration::infer(value) {
+                Some(value_declaration) => {
+                    match smod.op {
+                        Operator::SET => declaration.check(value)?,
+                        Operator::INSERT | Operator::REMOVE => {
+                            if let Some(items) = declaration.item_declaration() {
+                                items.check(value)?;
+                            }
+                        }
+                        _ => (),
+                    }
+                    validate_op(smod.var, &declaration, &value_declaration, smod.op)?;
+                }
+                None => return Err(verror!("Cannot set '{}' to {:?}.", smod.var, value)),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Validates that a character's colour and styles can be displayed.
+fn validate_character(character: &CharacterData) -> Result<(), ValidationError> {
+    if let Some(color) = &character.color {
+        if color.parse::<Color>().is_err() {
+            return Err(verror!("Unknown color '{}'.", color));
+        }
+    }
+    for style in &character.style {
+        if !STYLES.contains(&style.as_str()) {
+            return Err(verror!(
+                "Unknown style '{}', expected one of {}.",
+                style,
+                STYLES.join(", ")
+            ));
+        }
+    }
+    match &character.voice {
+        Some(voice) if !voice.contains("{id}") => Err(verror!(
+            "Voice '{}' does not contain '{{id}}' for the ID of each line.",
+            voice
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Validates that declarations are well formed and that initial values satisfy them.
+fn validate_declarations(config: &Config) -> Result<(), ValidationError> {
+    for (var, declaration) in &config.declarations {
+        let result = if declaration.value_type == ValueType::Enum && declaration.variants.is_empty()
+        {
+            Err(verror!("Enums must declare at least one variant."))
+        } else if declaration.min > declaration.max && declaration.max.is_some() {
+            Err(verror!("The minimum must not be greater than the maximum."))
+        } else {
+            declaration
+                .check(&declaration.default_value())
+                .and_then(|_| match config.state.get(var) {
+                    Some(value) => declaration.check(value),
+                    None => Ok(()),
+                })
+        };
+        if let Err(e) = result {
+            return Err(verror!("State variable '{}': {}", var, e));
+        }
     }
     Ok(())
 }
@@ -158,20 +508,612 @@ fn validate_goto(story: &Story, passage_name: &str) -> Result<(), ValidationErro
         Ok(())
     }
 }
-/// Validates that the story contains the referenced passage.
-fn validate_choices(story: &Story, choices: &Choices) -> Result<(), ValidationError> {
-    for (_choice, passage_name) in &choices.choices {
-        validate_goto(story, passage_name)?;
+/// Validates that the story contains the referenced passages and that any
+/// embedded set commands and echoed text are valid.
+fn validate_choices(
+    config: &Config,
+    story: &Story,
+    choices: &Choices,
+) -> Result<(), ValidationError> {
+    for (choice_text, choice) in &choices.choices {
+        let result = validate_goto(story, choice.passage())
+            .and_then(|_| match choice.set() {
+                Some(set) => validate_state(config, story, set),
+                None => Ok(()),
+            })
+            .and_then(|_| match choice.text() {
+                Some(text) => validate_text(config, story, text),
+                None => Ok(()),
+            });
+        if let Err(e) = result {
+            return Err(verror!("Choice '{}': {}", choice_text, e));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a passage is entered by a jump that can be returned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Transition {
+    Jump,
+    Call,
+}
+
+/// Collects every passage that can be entered from the given lines.
+fn transitions<'s>(lines: &'s [PassageLine], result: &mut Vec<(Transition, &'s str)>) {
+    for line in lines {
+        for block in line.blocks() {
+            transitions(block, result);
+        }
+        match line {
+            PassageLine::Choices(choices) => {
+                for choice in choices.choices.values() {
+                    result.push((Transition::Jump, choice.passage()));
+                }
+            }
+            PassageLine::Goto(goto) => result.push((Transition::Jump, &goto.goto)),
+            PassageLine::Call(call) => result.push((Transition::Call, &call.call)),
+            _ => (),
+        }
+    }
+}
+
+/// Collects the passages that are always entered once the given lines start running:
+/// top-level gotos and calls, up to the first line that leaves the passage or hands
+/// control to the player. Choices and the blocks of branching lines are not followed.
+fn unconditional_transitions<'s>(
+    lines: &'s [PassageLine],
+    result: &mut Vec<(Transition, &'s str)>,
+) {
+    for line in lines {
+        match line {
+            PassageLine::Goto(goto) => return result.push((Transition::Jump, &goto.goto)),
+            PassageLine::Call(call) => result.push((Transition::Call, &call.call)),
+            PassageLine::Choices(_) | PassageLine::Return(_) => return,
+            _ => (),
+        }
+    }
+}
+
+fn contains_return(lines: &[PassageLine]) -> bool {
+    lines.iter().any(|line| {
+        matches!(line, PassageLine::Return(_))
+            || line
+                .blocks()
+                .into_iter()
+                .any(|block| contains_return(block))
+    })
+}
+
+/// Validates that `return` is only reachable from inside a call.
+fn validate_returns(config: &Config, story: &Story) -> Result<(), ValidationError> {
+    let start_in_call = !config.stack.is_empty();
+    let mut visited = BTreeSet::new();
+    let mut queue = vec![(config.passage.as_str(), start_in_call)];
+    while let Some((passage_name, in_call)) = queue.pop() {
+        if !visited.insert((passage_name, in_call)) {
+            continue;
+        }
+        let passage = match story.get(passage_name) {
+            Some(passage) => passage,
+            None => continue,
+        };
+        if !in_call && contains_return(passage) {
+            return Err(verror!(
+                "Passage '{}': 'return' can be reached outside of any call.",
+                passage_name
+            ));
+        }
+        let mut next = vec![];
+        transitions(passage, &mut next);
+        for (transition, target) in next {
+            queue.push((target, in_call || transition == Transition::Call));
+        }
+    }
+    Ok(())
+}
+
+/// Validates that no passage always calls itself, directly or indirectly through
+/// gotos and calls that are always taken, which would grow the call stack forever.
+/// Loops that pass through a choice or a branch can be left, so they are allowed.
+fn validate_recursion(story: &Story) -> Result<(), ValidationError> {
+    let graph: Map<&str, Vec<(Transition, &str)>> = story
+        .iter()
+        .map(|(name, passage)| {
+            let mut next = vec![];
+            unconditional_transitions(passage, &mut next);
+            (name.as_str(), next)
+        })
+        .collect();
+    for (caller, edges) in &graph {
+        for (transition, callee) in edges {
+            if *transition != Transition::Call {
+                continue;
+            }
+            // Search for a path back to the caller starting at the callee.
+            let mut visited = BTreeSet::new();
+            let mut queue = vec![*callee];
+            while let Some(passage_name) = queue.pop() {
+                if passage_name == *caller {
+                    return Err(verror!(
+                        "Passage '{}': unbounded recursion through call to '{}'.",
+                        caller,
+                        callee
+                    ));
+                }
+                if visited.insert(passage_name) {
+                    if let Some(edges) = graph.get(passage_name) {
+                        queue.extend(edges.iter().map(|(_transition, target)| *target));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validates that no two lines share an ID.
+fn validate_ids(config: &Config, story: &Story) -> Result<(), ValidationError> {
+    let mut ids = BTreeSet::new();
+    for line in lines(config, story) {
+        if let Some(id) = line.id {
+            if !ids.insert(id) {
+                return Err(verror!(
+                    "Passage '{}': line ID '{}' is used more than once.",
+                    line.passage,
+                    id
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validates that the passages the config is in and will return to are in the story.
+fn validate_position(config: &Config, story: &Story) -> Result<(), ValidationError> {
+    let passages = std::iter::once(&config.passage).chain(config.stack.iter().map(|f| &f.passage));
+    for passage_name in passages {
+        if let Err(e) = validate_goto(story, passage_name) {
+            return Err(verror!("Config: {}", e));
+        }
     }
     Ok(())
 }
 
 // Validates an entire story for valid passage references, HTML, conditionals.
 pub fn validate(config: &Config, story: &Story) -> Result<(), ValidationError> {
+    validate_story(config, story, &mut Voices::default())
+}
+
+/// Validates an entire story, collecting the voice-over clips of its dialogue.
+fn validate_story(
+    config: &Config,
+    story: &Story,
+    voices: &mut Voices,
+) -> Result<(), ValidationError> {
+    validate_declarations(config)?;
+    validate_position(config, story)?;
+    for (name, character) in &config.characters {
+        if config.commands.contains_key(name) {
+            return Err(verror!("Character '{}' has the name of a command.", name));
+        }
+        if let Err(e) = validate_character(character) {
+            return Err(verror!("Character '{}': {}", name, e));
+        }
+    }
     for (passage_name, passage) in story {
-        if let Err(e) = validate_passage(config, story, passage) {
+        voices.passage = passage_name.clone();
+        if let Err(e) = validate_passage(config, story, passage, voices) {
             return Err(verror!("Passage '{}': {}", passage_name, e));
         }
     }
+    validate_ids(config, story)?;
+    validate_returns(config, story)?;
+    validate_recursion(story)
+}
+
+/// Validates that every line of the story is translated by the locale, listing lines
+/// without translations and translations that are stale or no longer in the story.
+pub fn validate_locale(
+    config: &Config,
+    story: &Story,
+    locale: &Locale,
+) -> Result<(), ValidationError> {
+    let mut untranslated = vec![];
+    let mut stale = vec![];
+    let mut ids = BTreeSet::new();
+    for line in lines(config, story) {
+        let id = match line.id {
+            Some(id) => id,
+            None => {
+                untranslated.push(format!(
+                    "Passage '{}': '{}' has no ID.",
+                    line.passage, line.source
+                ));
+                continue;
+            }
+        };
+        ids.insert(id);
+        match locale.translations.get(id) {
+            Some(row) if !row.translation.is_empty() => {
+                if row.source != line.source {
+                    stale.push(format!("'{}' was translated from '{}'.", id, row.source));
+                }
+                if let Err(e) = validate_text(config, story, &row.translation) {
+                    return Err(verror!("Translation '{}': {}", id, e));
+                }
+            }
+            _ => untranslated.push(format!("'{}' is not translated.", id)),
+        }
+    }
+    for id in locale.translations.keys() {
+        if !ids.contains(id.as_str()) {
+            stale.push(format!("'{}' is no longer in the story.", id));
+        }
+    }
+    if untranslated.is_empty() && stale.is_empty() {
+        return Ok(());
+    }
+    let report: Vec<String> = untranslated
+        .iter()
+        .chain(&stale)
+        .map(|line| format!("  {}", line))
+        .collect();
+    Err(verror!(
+        "{} untranslated and {} stale lines:\n{}",
+        untranslated.len(),
+        stale.len(),
+        report.join("\n")
+    ))
+}
+
+/// Collects the files in a directory and its subdirectories, relative to `root`.
+fn asset_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut BTreeSet<PathBuf>,
+) -> Result<(), ValidationError> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| verror!("Could not read '{}': {}", dir.display(), e))?;
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        } else if path.is_dir() {
+            asset_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            files.insert(relative.to_path_buf());
+        }
+    }
     Ok(())
 }
+
+/// Validates a story and that every line of dialogue has a voice-over clip in the
+/// assets directory, and that every file in it is used by a line.
+pub fn validate_assets(config: &Config, story: &Story, dir: &Path) -> Result<(), ValidationError> {
+    let mut voices = Voices::default();
+    validate_story(config, story, &mut voices)?;
+    let Voices {
+        clips, mut missing, ..
+    } = voices;
+    let mut files = BTreeSet::new();
+    asset_files(dir, dir, &mut files)?;
+    for clip in &clips {
+        if !files.contains(clip) {
+            missing.push(format!("'{}' is missing.", clip.display()));
+        }
+    }
+    let unused: Vec<String> = files
+        .difference(&clips)
+        .map(|file| format!("'{}' is not used by any line.", file.display()))
+        .collect();
+    if missing.is_empty() && unused.is_empty() {
+        return Ok(());
+    }
+    let report: Vec<String> = missing
+        .iter()
+        .chain(&unused)
+        .map(|line| format!("  {}", line))
+        .collect();
+    Err(verror!(
+        "{} lines without audio and {} unused audio files:\n{}",
+        missing.len(),
+        unused.len(),
+        report.join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::tests::{config, config_with, story};
+
+    #[test]
+    fn test_validate_calls() {
+        let returns = story(
+            r#"
+Start:
+  - call: Shop
+  - goto: Start
+Shop:
+  - Welcome to the shop.
+  - return:
+"#,
+        );
+        assert!(validate(&config(), &returns).is_ok());
+
+        let recursive = story(
+            r#"
+Start:
+  - call: Shop
+  - goto: Shop
+Shop:
+  - return:
+"#,
+        );
+        assert!(validate(&config(), &recursive).is_err());
+
+        let indirect = story(
+            r#"
+Start:
+  - call: Shop
+Shop:
+  - goto: Counter
+Counter:
+  - call: Shop
+"#,
+        );
+        assert!(validate(&config(), &indirect).is_err());
+
+        // Loops back to the caller through a choice or a branch can be left.
+        let escapable = story(
+            r#"
+Start:
+  - call: Shop
+Shop:
+  - choices:
+      buy: Counter
+      leave: Start
+Counter:
+  - if visits(Counter) > 1:
+      - goto: Start
+  - return:
+"#,
+        );
+        assert!(validate(&config(), &escapable).is_ok());
+    }
+
+    #[test]
+    fn test_validate_position() {
+        let story = story("{Start: [{call: Shop}], Shop: [Hi.]}");
+        assert!(validate(&config(), &story).is_ok());
+
+        let mut config = config();
+        config.passage = "story.Start".to_string();
+        assert!(validate(&config, &story).is_err());
+
+        let mut config = config_with("{passage: Shop, stack: [{passage: Nowhere, line: 1}]}");
+        assert!(validate(&config, &story).is_err());
+        config.stack[0].passage = "Start".to_string();
+        assert!(validate(&config, &story).is_ok());
+    }
+
+    #[test]
+    fn test_validate_declarations() {
+        let mut config = config_with(
+            r#"
+declarations:
+  mood: { type: enum, variants: [calm, angry] }
+  health: { type: number, min: 0, max: 10, default: 10 }
+"#,
+        );
+
+        let valid =
+            story("{Start: [{set: {mood =: angry, health -=: 20}}, {if mood == calm: []}]}");
+        assert!(validate(&config, &valid).is_ok());
+        assert!(validate(&config, &story("{Start: [{set: {mood =: sad}}]}")).is_err());
+        assert!(validate(&config, &story("{Start: [{set: {health =: 11}}]}")).is_err());
+        assert!(validate(&config, &story("{Start: [{if mood > 1: []}]}")).is_err());
+
+        // Floats may not sneak into integer variables.
+        config.state.insert("gold".to_string(), Value::Int(0));
+        assert!(validate(&config, &story("{Start: [{set: {gold +=: 2}}]}")).is_ok());
+        assert!(validate(&config, &story("{Start: [{set: {gold +=: 2.5}}]}")).is_err());
+        assert!(validate(&config, &story("{Start: [{set: {health =: 2}}]}")).is_ok());
+
+        // Collections are checked against their item types.
+        config.declarations.insert(
+            "inventory".to_string(),
+            serde_yaml::from_str("{type: set, items: string}").unwrap(),
+        );
+        let collections = "{Start: [{set: {inventory add: key, inventory remove: key}}, {if inventory has key: []}, {if len(inventory) >= 3: []}]}";
+        assert!(validate(&config, &story(collections)).is_ok());
+        assert!(validate(&config, &story("{Start: [{set: {inventory add: 1}}]}")).is_err());
+        assert!(validate(&config, &story("{Start: [{set: {gold add: key}}]}")).is_err());
+        assert!(validate(&config, &story("{Start: [{if len(gold) > 1: []}]}")).is_err());
+        assert!(validate(
+            &config,
+            &story("Start: ['You have {len(inventory)} items.']")
+        )
+        .is_ok());
+        assert!(validate(&config, &story("Start: ['You have {silver} silver.']")).is_err());
+
+        // Bounds are enforced when set commands run.
+        let mut runner = crate::runner::Runner::new(&mut config, &valid).unwrap();
+        if let PassageLine::SetCmd(cmd) = &valid["Start"][0] {
+            runner.set(&cmd.set).unwrap();
+        }
+        assert_eq!(runner.get("health"), Some(&Value::Number(0.)));
+        assert_eq!(
+            runner.get("mood"),
+            Some(&Value::String("angry".to_string()))
+        );
+
+        // Invalid values are rejected without changing the state.
+        let invalid: State = serde_yaml::from_str("{mood =: sleepy}").unwrap();
+        assert!(runner.set(&invalid).is_err());
+        let unknown: State = serde_yaml::from_str("{nope =: 5}").unwrap();
+        assert!(runner.set(&unknown).is_err());
+        assert_eq!(
+            runner.get("mood"),
+            Some(&Value::String("angry".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_validate_characters() {
+        let alice = story("Start: [{Alice: Hi.}]");
+        let character = |data: &str| -> Config {
+            let mut config = config();
+            config
+                .characters
+                .insert("Alice".to_string(), serde_yaml::from_str(data).unwrap());
+            config
+        };
+        assert!(validate(&character("description: Main character."), &alice).is_ok());
+        assert!(validate(
+            &character("{name: Alice B., color: bright blue, style: [bold]}"),
+            &alice
+        )
+        .is_ok());
+        assert!(validate(&character("color: octarine"), &alice).is_err());
+        assert!(validate(&character("style: [sparkly]"), &alice).is_err());
+
+        // Unquoted numbers and booleans said by a character are not commands.
+        for line in ["Start: [{Alice: 42}]", "Start: [{Alice: true}]"] {
+            let said = story(line);
+            let error = validate(&character("description: Main character."), &said).unwrap_err();
+            assert!(error.message.contains("must be text"), "{}", error);
+        }
+        assert_eq!(
+            character("name: Alice B.").characters["Alice"].display_name("Alice"),
+            "Alice B."
+        );
+    }
+
+    #[test]
+    fn test_validate_emotions() {
+        let mut config = config();
+        config.characters.insert(
+            "Alice".to_string(),
+            serde_yaml::from_str("emotions: [happy, angry]").unwrap(),
+        );
+        assert!(validate(&config, &story("Start: [{Alice (angry): Hey!}]")).is_ok());
+        assert!(validate(
+            &config,
+            &story("Start: [{Alice: {text: Hey!, emotion: happy, voice: a1, position: left}}]")
+        )
+        .is_ok());
+        assert!(validate(&config, &story("Start: [{Alice (sad): Hey!}]")).is_err());
+        assert!(validate(
+            &config,
+            &story("Start: [{Alice: {text: Hey!, emotion: sad}}]")
+        )
+        .is_err());
+        assert!(validate(
+            &config,
+            &story("Start: [{Alice (happy): {text: Hey!, emotion: angry}}]")
+        )
+        .is_err());
+        assert!(validate(&config, &story("Start: [{Bob (happy): Hey!}]")).is_err());
+        assert_eq!(split_speaker("Alice (angry)"), ("Alice", Some("angry")));
+        assert_eq!(split_speaker("Alice"), ("Alice", None));
+    }
+
+    #[cfg(feature = "locale")]
+    #[test]
+    fn test_validate_locale() {
+        let numbered = story("Start: [One. @line:one, Two. @line:two]");
+        let locale = |csv: &str| Locale::from_csv(csv).unwrap();
+        let complete = locale("id,source,translation\none,One.,Un.\ntwo,Two.,Deux.\n");
+        assert!(validate_locale(&config(), &numbered, &complete).is_ok());
+
+        let partial =
+            locale("id,source,translation\none,Uno.,Un.\ntwo,Two.,\nthree,Three.,Trois.\n");
+        let e = validate_locale(&config(), &numbered, &partial).unwrap_err();
+        assert!(e.message.starts_with("1 untranslated and 2 stale lines"));
+        assert!(e.message.contains("'two' is not translated."));
+        assert!(e.message.contains("'one' was translated from 'Uno.'."));
+        assert!(e.message.contains("'three' is no longer in the story."));
+
+        let broken = locale("id,source,translation\none,One.,Un {x}.\ntwo,Two.,Deux.\n");
+        assert!(validate_locale(&config(), &numbered, &broken).is_err());
+
+        let twice = story("{Start: [One. @line:one, {choices: {Two @line:one: Start}}]}");
+        assert!(validate(&config(), &twice).is_err());
+
+        // Choices with IDs are counted by their IDs.
+        let chosen = |condition: &str| -> Story {
+            let text = format!(
+                "{{Start: [{{choices: {{Wave @line:wave: Start, Leave: Start}}}}, {{'{}': []}}]}}",
+                condition
+            );
+            serde_yaml::from_str(&text).unwrap()
+        };
+        assert!(validate(&config(), &chosen("if chosen(Start, wave) > 0")).is_ok());
+        assert!(validate(&config(), &chosen("if chosen(Start, Leave) > 0")).is_ok());
+        assert!(validate(&config(), &chosen("if chosen(Start, Wave) > 0")).is_err());
+        assert!(validate(&config(), &chosen("if chosen(Start, Stay) > 0")).is_err());
+    }
+
+    #[test]
+    fn test_validate_assets() {
+        let mut config = config();
+        config.characters.insert(
+            "Alice".to_string(),
+            serde_yaml::from_str("voice: alice/{id}.ogg").unwrap(),
+        );
+        config
+            .characters
+            .insert("Bob".to_string(), CharacterData::default());
+        let voiced = story(
+            r#"
+Start:
+  - Alice: Hi! @line:hi
+  - if visits(Start) > 1:
+      - Alice: {text: Welcome back., voice: shared/welcome.ogg}
+  - Alice: Bye. @line:bye
+"#,
+        );
+        assert!(validate(&config, &voiced).is_ok());
+
+        let dir = std::env::temp_dir().join(format!("kataru-assets-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for file in &[
+            "alice/hi.ogg",
+            "alice/bye.ogg",
+            "shared/welcome.ogg",
+            ".hidden",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        assert!(validate_assets(&config, &voiced, &dir).is_ok());
+
+        fs::remove_file(dir.join("alice/bye.ogg")).unwrap();
+        fs::write(dir.join("alice/old.ogg"), "").unwrap();
+        let unvoiced =
+            story("Start: [Alice: Hi! @line:hi, Alice: Who? , Bob: Hello., Alice: Bye. @line:bye]");
+        let e = validate_assets(&config, &unvoiced, &dir).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(e
+            .message
+            .starts_with("3 lines without audio and 2 unused audio files"));
+        assert!(e
+            .message
+            .contains("Alice has no ID to find the audio of 'Who?' by."));
+        // Once there are assets, unvoiced characters are reported too.
+        assert!(e.message.contains("Bob has no voice for 'Hello.'"));
+        assert!(e.message.contains("'alice/bye.ogg' is missing."));
+        assert!(e
+            .message
+            .contains("'alice/old.ogg' is not used by any line."));
+        assert!(e
+            .message
+            .contains("'shared/welcome.ogg' is not used by any line."));
+
+        config.characters.get_mut("Bob").unwrap().voice = Some("bob.ogg".to_string());
+        assert!(validate(&config, &unvoiced).is_err());
+    }
+}
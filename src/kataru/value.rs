@@ -1,21 +1,57 @@
 use crate::error::ValidationError;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
 use std::ops::{AddAssign, SubAssign};
 
+/// A state value.
+///
+/// Integers and floating point numbers are distinct: arithmetic between two integers stays
+/// an integer, while mixing the two produces a float. Integers widen to floats when assigned
+/// to a float variable, but floats are never silently narrowed into integer variables.
+/// Comparisons between integers and floats compare their numeric values.
 #[derive(Debug, Clone, PartialEq, PartialOrd, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Value {
     None,
     String(String),
+    // Must come before Number so that whole numbers deserialize as integers.
+    Int(i64),
     Number(f64),
     Bool(bool),
+    List(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::None => Ok(()),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl AddAssign<&Self> for Value {
     fn add_assign(&mut self, rhs: &Self) {
         match (&self, rhs) {
-            (Value::Number(n1), Value::Number(n2)) => *self = Self::Number(n1 + n2),
-            _ => (),
+            (Value::Int(n1), Value::Int(n2)) => *self = Self::Int(n1.saturating_add(*n2)),
+            _ => {
+                if let (Some(n1), Some(n2)) = (self.as_f64(), rhs.as_f64()) {
+                    *self = Self::Number(n1 + n2)
+                }
+            }
         }
     }
 }
@@ -23,19 +59,63 @@ impl AddAssign<&Self> for Value {
 impl SubAssign<&Self> for Value {
     fn sub_assign(&mut self, rhs: &Self) {
         match (&self, rhs) {
-            (Value::Number(n1), Value::Number(n2)) => *self = Self::Number(*n1 - n2),
-            _ => *self = Self::None,
+            (Value::Int(n1), Value::Int(n2)) => *self = Self::Int(n1.saturating_sub(*n2)),
+            _ => match (self.as_f64(), rhs.as_f64()) {
+                (Some(n1), Some(n2)) => *self = Self::Number(n1 - n2),
+                _ => *self = Self::None,
+            },
         }
     }
 }
 
 impl Value {
     pub fn same_type(&self, rhs: &Self) -> bool {
+        matches!(
+            (self, rhs),
+            (Value::Bool(_), Value::Bool(_))
+                | (Value::Int(_), Value::Int(_))
+                | (Value::Number(_), Value::Number(_))
+                | (Value::String(_), Value::String(_))
+                | (Value::List(_), Value::List(_))
+        )
+    }
+
+    /// Whether both values can be compared, which also holds between integers and floats.
+    pub fn comparable(&self, rhs: &Self) -> bool {
+        self.same_type(rhs) || (self.as_f64().is_some() && rhs.as_f64().is_some())
+    }
+
+    /// Returns the numeric value of an integer or float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Compares two values, comparing integers and floats by their numeric values.
+    pub fn compare(&self, rhs: &Self) -> Option<Ordering> {
         match (self, rhs) {
-            (Value::Bool(_), Value::Bool(_)) => true,
-            (Value::Number(_), Value::Number(_)) => true,
-            (Value::String(_), Value::String(_)) => true,
-            _ => false,
+            (Value::Int(n1), Value::Int(n2)) => n1.partial_cmp(n2),
+            (Value::Int(_), Value::Number(_)) | (Value::Number(_), Value::Int(_)) => {
+                self.as_f64()?.partial_cmp(&rhs.as_f64()?)
+            }
+            _ => self.partial_cmp(rhs),
+        }
+    }
+
+    /// Whether two values are equal, treating integers and floats of the same value as equal.
+    pub fn equals(&self, rhs: &Self) -> bool {
+        self.compare(rhs) == Some(Ordering::Equal)
+    }
+
+    /// Converts a value assigned to a variable currently holding `target`,
+    /// widening integers assigned to float variables.
+    pub fn widened_like(&self, target: &Self) -> Self {
+        match (self, target) {
+            (Value::Int(n), Value::Number(_)) => Value::Number(*n as f64),
+            _ => self.clone(),
         }
     }
 
@@ -62,19 +142,104 @@ impl Value {
     //     }
     // }
 
-    fn from_yaml(yaml_value: serde_yaml::Value) -> Result<Self, ValidationError> {
-        match yaml_value {
-            serde_yaml::Value::Bool(b) => Ok(Value::Bool(b)),
-            serde_yaml::Value::String(s) => Ok(Value::String(s)),
-            serde_yaml::Value::Number(n) => Ok(Value::Number(n.as_f64().unwrap())),
-            _ => Err(verror!("Cannot create value from {:?}", yaml_value)),
+    /// Parses a value written in a story, following YAML: numbers, `true` and `false`,
+    /// quoted or plain strings, and lists such as `[a, 2]`.
+    pub fn parse(text: &str) -> Result<Value, ValidationError> {
+        let text = text.trim();
+        if let Some(items) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            if items.trim().is_empty() {
+                return Ok(Value::List(vec![]));
+            }
+            return split_items(items)
+                .into_iter()
+                .map(Self::parse)
+                .collect::<Result<_, _>>()
+                .map(Value::List);
+        }
+        if let Some(quoted) = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+            return Ok(Value::String(quoted.replace("''", "'")));
+        }
+        if let Some(quoted) = text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            return Ok(Value::String(
+                quoted.replace("\\\"", "\"").replace("\\\\", "\\"),
+            ));
+        }
+        let radix = |prefix: &str, radix: u32| {
+            let digits = text.strip_prefix(prefix)?;
+            i64::from_str_radix(digits, radix).ok()
+        };
+        if let Some(n) = radix("0x", 16).or_else(|| radix("0o", 8)) {
+            return Ok(Value::Int(n));
+        }
+        match text {
+            "" | "~" | "null" => Err(verror!("Cannot create value from '{}'", text)),
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => match (text.parse::<i64>(), text.parse::<f64>()) {
+                (Ok(n), _) => Ok(Value::Int(n)),
+                (_, Ok(n)) => Ok(Value::Number(n)),
+                _ => Ok(Value::String(text.to_string())),
+            },
         }
     }
+}
 
-    pub fn parse(text: &str) -> Result<Value, ValidationError> {
-        match serde_yaml::from_str(&text) {
-            Ok(r) => Self::from_yaml(r),
-            Err(e) => Err(verror!("{}", e)),
+/// Splits the items of a list on the commas outside of quotes and nested lists.
+fn split_items(text: &str) -> Vec<&str> {
+    let (mut items, mut start, mut depth, mut quote) = (vec![], 0, 0, None);
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('[', None) => depth += 1,
+            (']', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                items.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => (),
         }
     }
+    items.push(&text[start..]);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |text: &str| Value::parse(text).unwrap();
+        assert_eq!(parse("3"), Value::Int(3));
+        assert_eq!(parse("+3"), Value::Int(3));
+        assert_eq!(parse("0x1f"), Value::Int(31));
+        assert_eq!(parse("-2.5"), Value::Number(-2.5));
+        assert_eq!(parse("1e3"), Value::Number(1000.));
+        assert_eq!(parse("true"), Value::Bool(true));
+        assert_eq!(parse("calm"), Value::String("calm".to_string()));
+        assert_eq!(parse("'it''s'"), Value::String("it's".to_string()));
+        assert_eq!(parse("\"3\""), Value::String("3".to_string()));
+        assert_eq!(
+            parse("[a, 'b, c', [1, 2.5]]"),
+            Value::List(vec![
+                Value::String("a".to_string()),
+                Value::String("b, c".to_string()),
+                Value::List(vec![Value::Int(1), Value::Number(2.5)]),
+            ])
+        );
+        assert_eq!(parse("[]"), Value::List(vec![]));
+        assert!(Value::parse("~").is_err());
+        assert!(Value::parse("[1, null]").is_err());
+    }
+
+    #[test]
+    fn test_equals() {
+        assert!(Value::Int(1).equals(&Value::Number(1.)));
+        assert!(!Value::Int(1).equals(&Value::String("1".to_string())));
+
+        let mut list = Value::List(vec![Value::Number(1.), Value::Number(2.)]);
+        crate::operator::Operator::REMOVE.apply(&mut list, &Value::Int(1));
+        assert_eq!(list, Value::List(vec![Value::Number(2.)]));
+    }
 }
@@ -0,0 +1,207 @@
+use crossterm::terminal;
+use twine_terminal_rs::markup::{spans, Span};
+
+/// Width to wrap at when the terminal size is unknown, such as when piped.
+const DEFAULT_WIDTH: usize = 80;
+
+/// Joins the lines of each paragraph, such as those of multi-line YAML strings,
+/// keeping blank lines between paragraphs.
+pub fn reflow(text: &str) -> String {
+    text.trim_end()
+        .split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Counts the columns text takes up, skipping ANSI escape codes.
+pub fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip a control sequence up to its final byte.
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Finds where to break lines so that they fit in `width` columns, as byte indices.
+/// A space at a break is replaced by the line break, and any other character starts
+/// the next line, which is how words too long for a line are broken across lines.
+/// The first line starts at column `start`, and later lines are indented by `indent`.
+pub fn breaks(text: &str, width: usize, start: usize, indent: usize) -> Vec<usize> {
+    let mut breaks = vec![];
+    let mut column = start;
+    let mut space = None;
+    let mut word = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\n' => {
+                column = indent;
+                space = None;
+                word = 0;
+            }
+            ' ' if column >= width => {
+                breaks.push(i);
+                column = indent;
+                space = None;
+                word = 0;
+            }
+            ' ' => {
+                column += 1;
+                space = Some(i);
+                word = 0;
+            }
+            _ => {
+                if column >= width {
+                    if let Some(space) = space.take() {
+                        breaks.push(space);
+                        column = indent + word;
+                    }
+                }
+                // Break the word itself if it does not fit on a line of its own.
+                if column >= width && column > indent {
+                    breaks.push(i);
+                    column = indent;
+                    word = 0;
+                }
+                column += 1;
+                word += 1;
+            }
+        }
+    }
+    breaks
+}
+
+/// Inserts line breaks found by `breaks` into text starting at byte `offset` of the
+/// text that was broken, indenting each new line by `indent`.
+fn insert_breaks(text: &str, offset: usize, breaks: &[usize], indent: usize) -> String {
+    let mut broken = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        if c == '\n' || breaks.contains(&(offset + i)) {
+            broken.push('\n');
+            broken.push_str(&" ".repeat(indent));
+            if c != '\n' && c != ' ' {
+                broken.push(c);
+            }
+        } else {
+            broken.push(c);
+        }
+    }
+    broken
+}
+
+/// Returns the indent that lines wrapped after a name should hang at: under the text
+/// following the name, unless the name takes up too much of the width.
+pub fn hanging_indent(start: usize, width: usize) -> usize {
+    if start * 2 <= width {
+        start
+    } else {
+        2
+    }
+}
+
+/// Wraps plain text to lines, indenting all but the first line by `indent`.
+/// Words too long for a line are broken across lines, so that no line is wider than `width`.
+pub fn wrap(text: &str, width: usize, indent: usize) -> Vec<String> {
+    let width = width.max(1);
+    let indent = indent.min(width - 1);
+    let breaks = breaks(text, width, 0, indent);
+    insert_breaks(text, 0, &breaks, indent)
+        .split('\n')
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reflows and wraps marked up text to `width` columns, returning its spans with line
+/// breaks in place. The first line starts at column `start`, and later lines are
+/// indented by `indent`.
+pub fn lay_out(text: &str, width: usize, start: usize, indent: usize) -> Vec<Span> {
+    let mut spans = spans(&reflow(text));
+    let plain: String = spans.iter().map(|span| span.text.as_str()).collect();
+    let breaks = breaks(&plain, width, start, indent);
+    let mut offset = 0;
+    for span in &mut spans {
+        let text = insert_breaks(&span.text, offset, &breaks, indent);
+        offset += span.text.len();
+        span.text = text;
+    }
+    spans
+}
+
+/// Lays text out to the width of the terminal.
+#[derive(Default)]
+pub struct Layout {
+    /// Column to wrap at even if the terminal is wider.
+    pub max: Option<usize>,
+}
+
+impl Layout {
+    /// Returns the columns to wrap at, checked each time in case the terminal was resized.
+    pub fn width(&self) -> usize {
+        let width = terminal::size().map_or(DEFAULT_WIDTH, |(cols, _)| cols as usize);
+        self.max.map_or(width, |max| width.min(max)).max(1)
+    }
+
+    /// Reflows and wraps marked up text to the width of the terminal.
+    /// The first line starts at column `start`, and later lines are indented by `indent`.
+    pub fn lay_out(&self, text: &str, start: usize, indent: usize) -> Vec<Span> {
+        lay_out(text, self.width(), start, indent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            reflow("One\ntwo three\n\nFour.\n"),
+            "One two three\n\nFour."
+        );
+        assert_eq!(visible_width("\x1b[1;33mAlex\x1b[0m: "), 6);
+        assert_eq!(wrap("aaaa bbbbbb cc", 10, 0), vec!["aaaa", "bbbbbb cc"]);
+        assert_eq!(
+            wrap("Alex: one two three", 10, 6),
+            vec!["Alex: one", "      two", "      thre", "      e"]
+        );
+        assert_eq!(wrap("a\n\nb", 10, 0), vec!["a", "", "b"]);
+        assert_eq!(
+            wrap("see abcdefghijkl", 6, 2),
+            vec!["see", "  abcd", "  efgh", "  ijkl"]
+        );
+        assert_eq!(wrap("abc", 1, 4), vec!["a", "b", "c"]);
+        assert_eq!(hanging_indent(6, 80), 6);
+        assert_eq!(hanging_indent(30, 40), 2);
+        assert_eq!(breaks("one two", 10, 6, 0), vec![3]);
+        // Line mode breaks long words the same way.
+        assert_eq!(breaks("see abcdefghijkl", 6, 0, 2), vec![3, 8, 12]);
+        let laid_out: String = lay_out("see abcdefghijkl", 6, 0, 2)
+            .into_iter()
+            .map(|span| span.text)
+            .collect();
+        assert_eq!(laid_out, "see\n  abcd\n  efgh\n  ijkl");
+        let laid_out: Vec<(String, Vec<String>)> = lay_out("<b>one</b> two", 5, 0, 2)
+            .into_iter()
+            .map(|span| (span.text, span.tags))
+            .collect();
+        assert_eq!(
+            laid_out,
+            vec![
+                ("one".to_string(), vec!["b".to_string()]),
+                ("\n  two".to_string(), vec![])
+            ]
+        );
+    }
+}
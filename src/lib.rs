@@ -1 +1,2 @@
-
+pub mod kataru;
+pub use kataru::*;
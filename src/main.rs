@@ -1,74 +1,513 @@
-pub mod kataru;
+mod backlog;
+mod debugger;
+mod layout;
+mod pacing;
+mod tui;
+mod watch;
+
 use colored::*;
-use kataru::*;
+use layout::{hanging_indent, visible_width, Layout};
+use pacing::Pacing;
+use std::collections::BTreeSet;
+use std::fs;
 use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use structopt::StructOpt;
+use twine_terminal_rs::compile::is_compiled;
+use twine_terminal_rs::ids::assign_ids;
+use twine_terminal_rs::loader::story_files;
+use twine_terminal_rs::locale::{self, Locale};
+use twine_terminal_rs::*;
+use watch::Watch;
 
-fn get_input(input: &mut String) {
+#[derive(StructOpt)]
+#[structopt(about = "Plays a kataru story in the terminal.")]
+struct Opt {
+    /// Story file, manifest.yml listing story files, directory of story files, or compiled story.
+    #[structopt(parse(from_os_str), default_value = "story/story.yml")]
+    story: PathBuf,
+
+    /// Config file with the initial state and characters. Compiled stories bring their own.
+    #[structopt(short, long, parse(from_os_str), default_value = "story/config.yml")]
+    config: PathBuf,
+
+    /// Logs each line as it runs, with the branches taken and state changes.
+    #[structopt(long)]
+    trace: bool,
+
+    /// Runs the story in an interactive debugger.
+    #[structopt(long)]
+    debug: bool,
+
+    /// Reloads the story when its files change, keeping the current place and state.
+    #[structopt(long)]
+    watch: bool,
+
+    /// Plays the story full-screen, with a choice menu and a panel of state and characters.
+    #[structopt(long)]
+    tui: bool,
+
+    /// Reveals text one character at a time, taking MS milliseconds per character.
+    #[structopt(long, value_name = "MS")]
+    typewriter: Option<u64>,
+
+    /// Advances without waiting for Enter, at a reading speed of CPS characters per second.
+    #[structopt(long, value_name = "CPS", parse(try_from_str = pacing::parse_speed))]
+    auto: Option<f64>,
+
+    /// Wraps text at N columns at most, or at the terminal width if it is narrower.
+    #[structopt(long, value_name = "N")]
+    columns: Option<usize>,
+
+    /// Plays the story translated by the lines of a translation table.
+    #[structopt(long, parse(from_os_str), value_name = "FILE")]
+    locale: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
+
+#[derive(StructOpt)]
+enum Subcommand {
+    /// Writes the lines of the story with IDs to a CSV table to translate, keeping the
+    /// translations already in the table.
+    Extract {
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Validates the story and writes it with its config to OUTPUT, as compact JSON if
+    /// OUTPUT ends in `.json` and in the faster binary format otherwise.
+    Compile {
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Gives IDs to the lines of the story that have none, adding them to the story files.
+    AssignIds,
+    /// Validates the story and exits, failing if it or its translations or assets have
+    /// any problems.
+    Validate {
+        /// Checks that DIR has a voice-over clip for each line of dialogue, and nothing more.
+        #[structopt(long, parse(from_os_str), value_name = "DIR")]
+        assets: Option<PathBuf>,
+    },
+}
+
+/// Prints an error and exits.
+fn fail(e: impl std::fmt::Display) -> ! {
+    println!("{}", format!("{}", e).red());
+    process::exit(1);
+}
+
+fn new_runner<'r>(
+    config: &'r mut Config,
+    story: &'r Story,
+    locale: Option<&'r Locale>,
+) -> Runner<'r> {
+    let mut runner = Runner::new(config, story).unwrap_or_else(|e| fail(e));
+    if let Some(locale) = locale {
+        runner.localize(locale);
+    }
+    runner
+}
+
+/// Reports problems with the translations and voice-over clips of a story,
+/// exiting with an error if it has any problems.
+fn check(
+    config: &Config,
+    story: &Story,
+    valid: bool,
+    locale: Option<&Path>,
+    assets: Option<&Path>,
+) {
+    let mut valid = valid;
+    if let Some(path) = locale {
+        let locale = load_locale(path).unwrap_or_else(|e| fail(e));
+        if let Err(e) = validate_locale(config, story, &locale) {
+            println!("{}\n", format!("{}", e).yellow());
+            valid = false;
+        }
+    }
+    if let Some(dir) = assets {
+        if let Err(e) = validate_assets(config, story, dir) {
+            println!("{}\n", format!("{}", e).yellow());
+            valid = false;
+        }
+    }
+    if !valid {
+        process::exit(1);
+    }
+    println!("{}", "No problems found.".bold().green());
+}
+
+/// Adds IDs to the lines of each story file that lack them.
+fn assign(config: &Config, story: &Story, path: &Path) {
+    let mut used: BTreeSet<String> = locale::lines(config, story)
+        .iter()
+        .filter_map(|line| line.id.map(str::to_string))
+        .collect();
+    let mut total = 0;
+    for file in story_files(path).unwrap_or_else(|e| fail(e)) {
+        let result = fs::read_to_string(&file.path)
+            .map_err(|e| verror!("{}", e))
+            .and_then(|source| assign_ids(config, &source, &file.namespace, &mut used));
+        let (source, count) = match result {
+            Ok(result) => result,
+            Err(e) => fail(format!("File '{}': {}", file.path.display(), e)),
+        };
+        if count > 0 {
+            if let Err(e) = fs::write(&file.path, source) {
+                fail(format!("Could not write '{}': {}", file.path.display(), e));
+            }
+            println!("Added {} IDs to {}.", count, file.path.display());
+        }
+        total += count;
+    }
+    println!("{}", format!("Added {} line IDs.", total).bold().green());
+}
+
+/// Updates a translation table with the lines of the story.
+fn extract(config: &Config, story: &Story, output: &Path) {
+    let old = match output.exists() {
+        true => Some(load_locale(output).unwrap_or_else(|e| fail(e))),
+        false => None,
+    };
+    let locale = Locale::extract(config, story, old.as_ref());
+    let csv = locale.to_csv().unwrap_or_else(|e| fail(e));
+    if let Err(e) = fs::write(output, csv) {
+        fail(format!("Could not write '{}': {}", output.display(), e));
+    }
+    println!(
+        "{}",
+        format!(
+            "Wrote {} lines to {}.",
+            locale.translations.len(),
+            output.display()
+        )
+        .bold()
+        .green()
+    );
+}
+
+/// Writes the compiled story to a file.
+fn build(config: &Config, story: &Story, output: &Path) {
+    let bytes = compile(config, story, Format::from_path(output)).unwrap_or_else(|e| fail(e));
+    if let Err(e) = fs::write(output, &bytes) {
+        fail(format!("Could not write '{}': {}", output.display(), e));
+    }
+    println!(
+        "{}",
+        format!(
+            "Compiled story to {} ({} bytes).",
+            output.display(),
+            bytes.len()
+        )
+        .bold()
+        .green()
+    );
+}
+
+/// Lines read from stdin by a background thread, so that input can interrupt pauses.
+/// The thread starts on first use, leaving stdin alone in full-screen mode.
+fn input_lines() -> &'static Mutex<Receiver<String>> {
+    static LINES: OnceLock<Mutex<Receiver<String>>> = OnceLock::new();
+    LINES.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut line = String::new();
+            match stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if sender.send(line).is_err() => break,
+                Ok(_) => (),
+            }
+        });
+        Mutex::new(receiver)
+    })
+}
+
+/// Reads a line of input, giving up after `timeout` if one is given.
+fn read_line(timeout: Option<Duration>) -> Option<String> {
     let _ = stdout().flush();
-    *input = String::new();
-    stdin().read_line(input).expect("Invalid input");
+    let lines = input_lines().lock().unwrap();
+    let line = match timeout {
+        Some(timeout) => match lines.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => {
+                // Without input, timed pauses still take their time.
+                thread::sleep(timeout);
+                return None;
+            }
+            Ok(line) => Some(line),
+        },
+        None => lines.recv().ok(),
+    };
+    match line {
+        Some(line) => Some(line.trim_end_matches(&['\n', '\r'][..]).to_string()),
+        // Nothing more can be read, so there is no one left to play.
+        None => process::exit(0),
+    }
+}
+
+fn get_input(input: &mut String) {
+    *input = read_line(None).unwrap_or_default();
+}
+
+/// Finds the choice entered by its number, its text or its ID.
+fn pick(choices: &[Line], input: &str) -> Result<usize, ValidationError> {
+    match input.parse::<usize>() {
+        Ok(number) if number >= 1 && number <= choices.len() => Ok(number - 1),
+        _ => choices
+            .iter()
+            .position(|choice| choice.text == input || choice.id.as_deref() == Some(input))
+            .ok_or_else(|| verror!("Invalid choice '{}'.", input)),
+    }
+}
+
+/// Styles a speaker's name with their character's colour and styles.
+fn speaker(name: &str, character: Option<&CharacterData>) -> ColoredString {
+    let character = match character {
+        Some(character) if character.color.is_some() || !character.style.is_empty() => character,
+        Some(character) => return character.display_name(name).bold().yellow(),
+        None => return name.bold().yellow(),
+    };
+    let styles = character.color.iter().chain(&character.style);
+    styles.fold(character.display_name(name).normal(), |styled, style| {
+        apply_style(styled, style)
+    })
+}
+
+/// Applies a character style or markup tag, such as `bold`, `i` or `bright blue`.
+fn apply_style(styled: ColoredString, style: &str) -> ColoredString {
+    match style {
+        "b" | "bold" => styled.bold(),
+        "dimmed" => styled.dimmed(),
+        "i" | "em" | "italic" => styled.italic(),
+        "u" | "underline" => styled.underline(),
+        "blink" => styled.blink(),
+        "reversed" => styled.reversed(),
+        "hidden" => styled.hidden(),
+        "s" | "strikethrough" => styled.strikethrough(),
+        color => match color.parse::<Color>() {
+            Ok(color) => styled.color(color),
+            Err(_) => styled,
+        },
+    }
+}
+
+/// Styles narration, which is italic unless marked up otherwise.
+fn narration(text: &str, tags: &[String]) -> ColoredString {
+    tags.iter()
+        .fold(text.italic(), |styled, tag| apply_style(styled, tag))
+}
+
+fn speech(text: &str, tags: &[String]) -> ColoredString {
+    tags.iter()
+        .fold(text.normal(), |styled, tag| apply_style(styled, tag))
+}
+
+/// Prints an event for the player, rendering the markup of text and wrapping it.
+fn show(runner: &Runner, event: &Event, pacing: &Pacing, layout: &Layout) {
+    match event {
+        Event::Text(text) => {
+            pacing.reveal(&layout.lay_out(&text.text, 0, 0), &narration);
+            println!();
+        }
+        Event::Dialogue { character, speech } => {
+            let data = runner.character(character);
+            let prefix = data.map_or("", |data| data.prefix.as_str());
+            let emotion = match &speech.emotion {
+                Some(emotion) => format!(" ({})", emotion).dimmed(),
+                None => "".normal(),
+            };
+            let name = format!("{}{}: {}", speaker(character, data), emotion, prefix);
+            // Indent wrapped lines under the text, unless the name takes up too much room.
+            let start = visible_width(&name);
+            let indent = hanging_indent(start, layout.width());
+            print!("{}", name);
+            pacing.reveal(&layout.lay_out(&speech.text, start, indent), &self::speech);
+            println!();
+        }
+        Event::Choices(choices) => {
+            for (i, choice) in choices.iter().enumerate() {
+                println!("{} {}", format!("{}.", i + 1).dimmed(), choice.text.cyan());
+            }
+        }
+        Event::Command(command) => println!("{}", format!("[{}]", command).dimmed()),
+        Event::Error(message) => println!("{}", message.red()),
+        _ => (),
+    }
+}
+
+/// Runs a player command, such as `:log` to page through what has happened so far.
+/// Returns false if the input is not a command.
+fn player_command(runner: &Runner, input: &str) -> bool {
+    if !input.starts_with(':') {
+        return false;
+    }
+    let (command, args) = input.split_once(' ').unwrap_or((input, ""));
+    match command {
+        ":log" => backlog::page(runner, args.trim()),
+        _ => println!(
+            "{}",
+            format!("Unknown command '{}'. Try `:log [search]`.", command).magenta()
+        ),
+    }
+    true
+}
+
+/// Asks for a choice until a valid one is made.
+fn prompt_choice(runner: &mut Runner, choices: &[Line], input: &mut String) {
+    print!("{}", "Enter your choice: ".magenta());
+    get_input(input);
     loop {
-        if let Some('\n') = input.chars().next_back() {
-            input.pop();
-        } else if let Some('\r') = input.chars().next_back() {
-            input.pop();
+        if player_command(runner, input) {
+            print!("{}", "Enter your choice: ".magenta());
+        } else if let Err(e) = pick(choices, input).and_then(|i| runner.choose(i)) {
+            print!("{}", format!("{} Try again: ", e).magenta());
         } else {
-            break;
+            return;
         }
+        get_input(input);
     }
 }
 
-fn await_key(input: &mut String) {
-    get_input(input);
-    *input = String::new();
+/// Plays the story until it ends, or until it is reloaded and returned with the config.
+fn play(
+    runner: &mut Runner,
+    pacing: &Pacing,
+    layout: &Layout,
+    mut watch: Option<&mut Watch>,
+) -> Option<(Story, Config)> {
+    let mut input = String::new();
+    loop {
+        // Only reload between lines, once the events of the last line have been shown.
+        let event = match runner.poll() {
+            Some(event) => event,
+            None => {
+                if let Some(reloaded) = watch.as_mut().and_then(|watch| watch.reload(runner)) {
+                    return Some(reloaded);
+                }
+                runner.advance()
+            }
+        };
+        show(runner, &event, pacing, layout);
+        let text = match event {
+            Event::Text(line) => line.text,
+            Event::Dialogue { speech, .. } => speech.text,
+            Event::Choices(choices) => {
+                prompt_choice(runner, &choices, &mut input);
+                continue;
+            }
+            Event::End => return None,
+            _ => continue,
+        };
+        // Commands typed instead of continuing run before waiting again.
+        let chars = markup::plain(&text).chars().count();
+        while let Some(input) = pacing.wait(chars) {
+            if !player_command(runner, &input) {
+                break;
+            }
+        }
+    }
 }
 
 fn main() {
+    let opt = Opt::from_args();
+
+    // Log to stderr so that traces stay out of transcripts. RUST_LOG picks other levels.
+    let mut logger = env_logger::Builder::from_default_env();
+    if opt.trace {
+        logger.filter_module("twine_terminal_rs", log::LevelFilter::Trace);
+    }
+    logger.format_timestamp(None).init();
+
     // Load the story.
     println!("{}", "Loading story...".bold().cyan());
-    let story_str = include_str!("../story/story.yml");
-    let config_str = include_str!("../story/config.yml");
-    let story: Story = serde_yaml::from_str(&story_str).unwrap();
-    let mut config: Config = serde_yaml::from_str(&config_str).unwrap();
-    let mut runner = Runner::new(&mut config, &story);
+    let compiled = is_compiled(&opt.story);
+    let loaded = match compiled {
+        true => load_compiled(&opt.story).map(|(config, story)| (story, config)),
+        false => load_story(&opt.story).and_then(|story| Ok((story, load_config(&opt.config)?))),
+    };
+    let (story, mut config) = loaded.unwrap_or_else(|e| fail(e));
+    config.init_state();
 
     // Validate the story.
     println!("{}", "Validating story...".bold().cyan());
-    let msg = match validate(&runner.config, &runner.story) {
+    let valid = validate(&config, &story);
+    let msg = match &valid {
         Err(e) => format!("{}", e).red(),
         Ok(_) => "Validated story successfully.".bold().green(),
     };
     println!("{}\n", msg);
 
-    let mut input = String::new();
+    match &opt.command {
+        Some(Subcommand::Validate { assets }) => {
+            let (locale, assets) = (opt.locale.as_deref(), assets.as_deref());
+            return check(&config, &story, valid.is_ok(), locale, assets);
+        }
+        Some(Subcommand::Extract { output }) => return extract(&config, &story, output),
+        Some(Subcommand::Compile { output }) => match valid {
+            Ok(_) => return build(&config, &story, output),
+            Err(_) => process::exit(1),
+        },
+        Some(Subcommand::AssignIds) => return assign(&config, &story, &opt.story),
+        None => (),
+    }
+    if config.rng == Rng::default() {
+        // Seed fresh playthroughs from the clock; saved configs keep their own seed.
+        // This comes after compiling so that compiled stories are seeded when played.
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        config.rng = Rng::new(seed);
+    }
+    let locale = opt.locale.as_ref().map(|path| {
+        let locale = load_locale(path).unwrap_or_else(|e| fail(e));
+        if let Err(e) = validate_locale(&config, &story, &locale) {
+            println!("{}\n", format!("{}", e).yellow());
+        }
+        locale
+    });
+
+    if opt.debug {
+        debugger::debug(&mut new_runner(&mut config, &story, locale.as_ref()));
+        return;
+    }
+    if opt.tui {
+        if let Err(e) = tui::play(&mut new_runner(&mut config, &story, locale.as_ref())) {
+            fail(e);
+        }
+        return;
+    }
+    let pacing = Pacing {
+        delay: opt.typewriter.map(Duration::from_millis),
+        speed: opt.auto,
+    };
+    let layout = Layout { max: opt.columns };
+    let mut story = story;
+    if opt.watch && compiled {
+        println!("{}\n", "Compiled stories are not watched.".yellow());
+    }
+    let mut watch = if opt.watch && !compiled {
+        Some(Watch::new(&opt.story))
+    } else {
+        None
+    };
+    // Each reload restarts the runner on the new story from where the old one left off.
     loop {
-        match runner.next(&input) {
-            Some(line) => match &line {
-                PassageLine::Text(text) => {
-                    println!("{}", text.italic());
-                    await_key(&mut input);
-                }
-                PassageLine::Dialogue(dialogue) => {
-                    let (name, quote) = dialogue.iter().next().unwrap();
-                    println!("{}: {}", name.bold().yellow(), quote);
-                    await_key(&mut input);
-                }
-                PassageLine::Choices(choices) => {
-                    for (choice, _passage_name) in &choices.choices {
-                        println!("{}", choice.cyan());
-                    }
-                    print!("{}", "Enter your choice: ".magenta());
-                    get_input(&mut input);
-                }
-                PassageLine::InvalidChoice => {
-                    print!(
-                        "{}",
-                        format!("Invalid choice '{}', try again: ", input).magenta()
-                    );
-                    get_input(&mut input);
-                }
-                _ => (),
-            },
+        let reloaded = {
+            let mut runner = new_runner(&mut config, &story, locale.as_ref());
+            play(&mut runner, &pacing, &layout, watch.as_mut())
+        };
+        match reloaded {
+            Some((reloaded, saved)) => {
+                story = reloaded;
+                config = saved;
+            }
             None => break,
         }
     }
@@ -0,0 +1,87 @@
+use crate::read_line;
+use colored::*;
+use std::io::{stdout, Write};
+use std::time::Duration;
+use twine_terminal_rs::markup::Span;
+
+/// How fast text appears and moves on.
+#[derive(Default)]
+pub struct Pacing {
+    /// Time to reveal each character, or None to show lines at once.
+    pub delay: Option<Duration>,
+    /// Reading speed in characters per second for advancing without input,
+    /// or None to wait for Enter.
+    pub speed: Option<f64>,
+}
+
+/// How many characters' worth of time to pause after a character.
+fn pause_after(c: char) -> u32 {
+    match c {
+        '.' | '!' | '?' => 8,
+        ',' | ';' | ':' | '\n' => 4,
+        _ => 1,
+    }
+}
+
+/// Parses a reading speed in characters per second, which must be positive.
+pub fn parse_speed(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(speed) if speed > 0. && speed.is_finite() => Ok(speed),
+        Ok(_) => Err(format!("The reading speed must be positive, not {}.", text)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl Pacing {
+    /// Prints styled spans, one character at a time if a delay is set.
+    /// Pressing Enter skips to the full text.
+    pub fn reveal(&self, spans: &[Span], style: &dyn Fn(&str, &[String]) -> ColoredString) {
+        let delay = match self.delay {
+            Some(delay) => delay,
+            None => {
+                for span in spans {
+                    print!("{}", style(&span.text, &span.tags));
+                }
+                return;
+            }
+        };
+        let mut skipped = false;
+        for span in spans {
+            if skipped {
+                print!("{}", style(&span.text, &span.tags));
+                continue;
+            }
+            for (i, c) in span.text.char_indices() {
+                print!("{}", style(&c.to_string(), &span.tags));
+                let _ = stdout().flush();
+                if read_line(Some(delay * pause_after(c))).is_some() {
+                    skipped = true;
+                    print!("{}", style(&span.text[i + c.len_utf8()..], &span.tags));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Waits for Enter, or in auto mode for as long as it takes to read `chars` characters.
+    pub fn wait(&self, chars: usize) -> Option<String> {
+        match self.speed {
+            Some(speed) => read_line(Some(Duration::from_secs_f64(chars as f64 / speed + 0.5))),
+            None => read_line(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("12.5"), Ok(12.5));
+        assert!(parse_speed("0").is_err());
+        assert!(parse_speed("-3").is_err());
+        assert!(parse_speed("NaN").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use crate::layout::{hanging_indent, lay_out, reflow, wrap};
+use crate::{narration, speaker, speech};
+use colored::*;
+use crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind};
+use crossterm::style::Print;
+use crossterm::terminal::{self, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, queue};
+use std::io::{self, stdout, Write};
+use twine_terminal_rs::markup::Span;
+use twine_terminal_rs::*;
+
+/// Columns of the side panel on wide terminals.
+const PANEL_WIDTH: usize = 32;
+
+/// Something shown in the transcript.
+/// Narration and dialogue keep their markup, which is rendered when drawn.
+enum Entry {
+    Text(String),
+    Dialogue {
+        name: ColoredString,
+        plain_name: String,
+        text: String,
+    },
+    /// A choice the player made.
+    Chosen(String),
+    /// A message from the player itself, such as a command or the end of the story.
+    Note(String),
+}
+
+/// What a key press asks for.
+enum Action {
+    Continue,
+    Quit,
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Renders laid out spans to rows, styling the text of each span by its markup.
+fn styled_rows(spans: &[Span], style: &dyn Fn(&str, &[String]) -> ColoredString) -> Vec<String> {
+    let mut rows = vec![String::new()];
+    for span in spans {
+        for (i, piece) in span.text.split('\n').enumerate() {
+            if i > 0 {
+                rows.push(String::new());
+            }
+            if !piece.is_empty() {
+                let row = rows.last_mut().unwrap();
+                row.push_str(&style(piece, &span.tags).to_string());
+            }
+        }
+    }
+    rows
+}
+
+struct Tui {
+    transcript: Vec<Entry>,
+    /// Choices waiting to be made, with the one currently selected.
+    choices: Option<(Vec<String>, usize)>,
+    /// Number of transcript rows scrolled up from the bottom.
+    scroll: usize,
+}
+
+impl Tui {
+    /// Renders the transcript to rows of the given width.
+    fn transcript_rows(&self, width: usize) -> Vec<String> {
+        let mut rows = vec![];
+        for entry in &self.transcript {
+            match entry {
+                Entry::Text(text) => {
+                    rows.extend(styled_rows(&lay_out(text, width, 0, 0), &narration))
+                }
+                Entry::Dialogue {
+                    name,
+                    plain_name,
+                    text,
+                } => {
+                    let start = plain_name.chars().count() + 2;
+                    let indent = hanging_indent(start, width);
+                    let mut wrapped = styled_rows(&lay_out(text, width, start, indent), &speech);
+                    wrapped[0] = format!("{}: {}", name, wrapped[0]);
+                    rows.extend(wrapped);
+                }
+                Entry::Chosen(text) => rows.extend(
+                    wrap(&format!("> {}", text), width, 2)
+                        .iter()
+                        .map(|row| row.cyan().to_string()),
+                ),
+                Entry::Note(text) => rows.extend(
+                    wrap(text, width, 0)
+                        .iter()
+                        .map(|row| row.dimmed().to_string()),
+                ),
+            }
+            rows.push(String::new());
+        }
+        rows
+    }
+
+    /// Renders the side panel with the state variables and characters.
+    fn panel_rows(&self, runner: &Runner, width: usize) -> Vec<String> {
+        let mut rows = vec!["State".bold().to_string()];
+        for (var, value) in runner.state() {
+            rows.push(truncate(&format!("{} = {}", var, value), width));
+        }
+        rows.push(String::new());
+        rows.push("Characters".bold().to_string());
+        for (key, character) in runner.characters() {
+            let name = truncate(character.display_name(key), width);
+            rows.push(speaker(&name, Some(character)).to_string());
+            for row in wrap(&reflow(&character.description), width.saturating_sub(2), 0) {
+                rows.push(format!("  {}", row).dimmed().to_string());
+            }
+        }
+        rows
+    }
+
+    fn draw(&self, runner: &Runner) -> io::Result<()> {
+        let (cols, rows) = terminal::size()?;
+        let (cols, rows) = (cols as usize, rows as usize);
+        // Hide the side panel when there is no room for it.
+        let panel = if cols >= PANEL_WIDTH * 2 {
+            PANEL_WIDTH
+        } else {
+            0
+        };
+        let width = cols - panel - if panel > 0 { 1 } else { 0 };
+        let height = rows.saturating_sub(1);
+
+        let mut menu = vec![];
+        if let Some((choices, selected)) = &self.choices {
+            for (i, choice) in choices.iter().enumerate() {
+                let row = truncate(
+                    &format!(
+                        "{} {}. {}",
+                        if i == *selected { ">" } else { " " },
+                        i + 1,
+                        choice
+                    ),
+                    width,
+                );
+                menu.push(if i == *selected {
+                    row.cyan().reversed().to_string()
+                } else {
+                    row.cyan().to_string()
+                });
+            }
+        }
+        let pane = height.saturating_sub(menu.len());
+        let transcript = self.transcript_rows(width);
+        let end = transcript
+            .len()
+            .saturating_sub(self.scroll.min(transcript.len().saturating_sub(pane)));
+        let start = end.saturating_sub(pane);
+        let mut left: Vec<&str> = transcript[start..end].iter().map(String::as_str).collect();
+        left.resize(pane, "");
+        left.extend(menu.iter().map(String::as_str));
+        let right = self.panel_rows(runner, panel.saturating_sub(1));
+
+        let mut out = stdout();
+        queue!(out, terminal::Clear(ClearType::All))?;
+        for (y, row) in left.iter().enumerate().take(height) {
+            queue!(out, cursor::MoveTo(0, y as u16), Print(row))?;
+            if panel > 0 {
+                let side = right.get(y).map_or("", String::as_str);
+                queue!(
+                    out,
+                    cursor::MoveTo(width as u16, y as u16),
+                    Print("│ ".dimmed()),
+                    Print(side)
+                )?;
+            }
+        }
+        let (passage, line) = runner.position();
+        let status = format!(
+            " {} line {}   ↑↓ select  Enter continue  PgUp/PgDn scroll  q quit",
+            passage, line
+        );
+        let status = format!("{:width$}", truncate(&status, cols), width = cols);
+        queue!(
+            out,
+            cursor::MoveTo(0, height as u16),
+            Print(status.reversed())
+        )?;
+        out.flush()
+    }
+
+    /// Redraws until a key continues the story or quits.
+    fn wait(&mut self, runner: &mut Runner) -> io::Result<Action> {
+        loop {
+            self.draw(runner)?;
+            let key = match event::read()? {
+                TermEvent::Key(key) if key.kind != KeyEventKind::Release => key,
+                _ => continue,
+            };
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Action::Quit),
+                KeyCode::PageUp => self.scroll += 5,
+                KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(5),
+                KeyCode::Up | KeyCode::Down if self.choices.is_some() => {
+                    let (choices, selected) = self.choices.as_mut().unwrap();
+                    *selected = match key.code {
+                        KeyCode::Up => (*selected + choices.len() - 1) % choices.len(),
+                        _ => (*selected + 1) % choices.len(),
+                    };
+                }
+                KeyCode::Char(c) if self.choices.is_some() && c.is_ascii_digit() => {
+                    let (choices, selected) = self.choices.as_mut().unwrap();
+                    let index = c.to_digit(10).unwrap() as usize;
+                    if index >= 1 && index <= choices.len() {
+                        *selected = index - 1;
+                    }
+                }
+                KeyCode::Enter | KeyCode::Char(' ') => {
+                    self.scroll = 0;
+                    if let Some((choices, selected)) = self.choices.take() {
+                        runner.choose(selected).unwrap();
+                        self.transcript
+                            .push(Entry::Chosen(choices[selected].clone()));
+                    }
+                    return Ok(Action::Continue);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn play(&mut self, runner: &mut Runner) -> io::Result<()> {
+        loop {
+            let event = runner.advance();
+            let ended = event == Event::End;
+            match event {
+                Event::Text(line) => self.transcript.push(Entry::Text(line.text)),
+                Event::Dialogue { character, speech } => {
+                    let data = runner.character(&character);
+                    let plain_name = data
+                        .map_or(character.as_str(), |data| data.display_name(&character))
+                        .to_string();
+                    let prefix = data.map_or("", |data| data.prefix.as_str());
+                    let text = match &speech.emotion {
+                        Some(emotion) => format!("({}) {}{}", emotion, prefix, speech.text),
+                        None => format!("{}{}", prefix, speech.text),
+                    };
+                    self.transcript.push(Entry::Dialogue {
+                        name: speaker(&character, data),
+                        plain_name,
+                        text,
+                    });
+                }
+                Event::Choices(choices) => {
+                    let texts = choices.into_iter().map(|choice| choice.text).collect();
+                    self.choices = Some((texts, 0));
+                }
+                Event::Command(command) => {
+                    self.transcript.push(Entry::Note(format!("[{}]", command)));
+                    continue;
+                }
+                Event::Error(message) => {
+                    self.transcript.push(Entry::Note(message));
+                    continue;
+                }
+                Event::End => self.transcript.push(Entry::Note("The end.".to_string())),
+                _ => continue,
+            }
+            if let Action::Quit = self.wait(runner)? {
+                return Ok(());
+            }
+            if ended {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Restores the terminal when dropped, even if the player panics.
+struct Screen;
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let screen = Self;
+        queue!(stdout(), EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        // Errors cannot be reported from here, and the terminal is restored as far as possible.
+        let _ = queue!(stdout(), cursor::Show, LeaveAlternateScreen);
+        let _ = stdout().flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Plays the story full-screen until it ends or the player quits.
+pub fn play(runner: &mut Runner) -> io::Result<()> {
+    let mut tui = Tui {
+        transcript: vec![],
+        choices: None,
+        scroll: 0,
+    };
+    let _screen = Screen::enter()?;
+    tui.play(runner)
+}
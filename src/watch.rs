@@ -0,0 +1,72 @@
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use twine_terminal_rs::loader::story_files;
+use twine_terminal_rs::*;
+
+/// Watches the files of a story for changes.
+pub struct Watch {
+    path: PathBuf,
+    files: Vec<PathBuf>,
+    stamps: Vec<Option<SystemTime>>,
+}
+
+/// Lists the story path itself, such as a manifest or directory, and the files it includes.
+fn watched_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    if let Ok(story_files) = story_files(path) {
+        files.extend(story_files.into_iter().map(|file| file.path));
+    }
+    files
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+impl Watch {
+    pub fn new(path: &Path) -> Self {
+        let files = watched_files(path);
+        let stamps = files.iter().map(|file| modified(file)).collect();
+        Self {
+            path: path.to_path_buf(),
+            files,
+            stamps,
+        }
+    }
+
+    /// Returns true if any watched file changed since the last call.
+    fn changed(&mut self) -> bool {
+        let stamps: Vec<_> = self.files.iter().map(|file| modified(file)).collect();
+        if stamps == self.stamps {
+            return false;
+        }
+        self.stamps = stamps;
+        true
+    }
+
+    /// Loads the story again if it changed and can replace the running one,
+    /// returning it with the runner's config moved onto it.
+    /// Prints why the story could not be reloaded.
+    pub fn reload(&mut self, runner: &mut Runner) -> Option<(Story, Config)> {
+        if !self.changed() {
+            return None;
+        }
+        let mut config = runner.save().clone();
+        let result = load_story(&self.path)
+            .and_then(|story| reload(&mut config, runner.story, &story).map(|_| story));
+        match result {
+            Ok(story) => {
+                println!("{}", "Reloaded story.".bold().green());
+                // The reloaded story may include different files.
+                *self = Self::new(&self.path);
+                Some((story, config))
+            }
+            Err(e) => {
+                println!("{}", format!("Kept the previous story: {}", e).red());
+                None
+            }
+        }
+    }
+}